@@ -1,46 +1,170 @@
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 use pixels::wgpu::Color;
 use pixels::{Error, Pixels, SurfaceTexture};
 use winit::dpi::LogicalSize;
-use winit::event::{Event, VirtualKeyCode, WindowEvent};
-use winit::event_loop::EventLoop;
+use winit::event::{Event, ModifiersState, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 
 use crate::display::Display;
-use crate::{CHIP8Input, InputKey, OldBehaviourConfig};
+use crate::rewind::Rewind;
+use crate::{CHIP8Input, InputKey, Status, CHIP8, DISPLAY_HEIGHT, DISPLAY_WIDTH};
 
 const SCALING: u64 = 10;
 
+/// The emulation and the display both run at 60 frames per second.
+const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// How far the schedule may fall behind (e.g. while the window is being
+/// dragged) before it gives up catching up and starts afresh.
+const MAX_FRAME_LAG: u32 = 4;
+
+/// `fg_on_color` is used for pixels lit only in the first plane,
+/// `fg_plane2_color` for the XO-CHIP second plane and `fg_both_color` where
+/// both overlap.
 #[derive(Copy, Clone)]
 pub struct ColorConfig {
     pub fg_on_color: (u8, u8, u8),
     pub fg_off_color: (u8, u8, u8),
+    pub fg_plane2_color: (u8, u8, u8),
+    pub fg_both_color: (u8, u8, u8),
     pub bg_color: (u8, u8, u8),
 }
 
+#[derive(Copy, Clone)]
+pub enum Timing {
+    /// A fixed number of instructions every 60Hz frame.
+    InstructionsPerFrame(usize),
+    /// Every 60Hz frame runs the instructions that fit in the COSMAC VIP's
+    /// cycle budget, see `CHIP8::run_vip_frame`.
+    Vip,
+}
+
+/// Next speed for the `+`/`-` hotkeys: steps of a quarter, at least one
+/// instruction, within 1..=10000.
+fn adjust_speed(ipf: usize, faster: bool) -> usize {
+    let step = (ipf / 4).max(1);
+    if faster {
+        (ipf + step).min(10000)
+    } else {
+        ipf.saturating_sub(step).max(1)
+    }
+}
+
+/// Inspects and controls the machine between frames, such as the terminal
+/// debugger of `chip8 debug`.
+pub trait Debugger {
+    /// Called before every frame. May step or change the machine, and pause
+    /// or resume it through `paused`. Returns false to quit.
+    fn frame(&mut self, chip8: &mut CHIP8, input: &CHIP8Input, paused: &mut bool) -> bool;
+
+    /// Takes the messages that would otherwise be printed over its screen.
+    fn message(&mut self, text: String);
+
+    /// Called when the program exits or emulation fails, after which the
+    /// machine no longer runs.
+    fn halted(&mut self, _exited: bool) {}
+}
+
+/// Prints a message, or hands it to the debugger if there is one.
+fn report(debugger: &mut Option<Box<dyn Debugger>>, text: String, error: bool) {
+    match debugger {
+        Some(debugger) => debugger.message(text),
+        None if error => eprintln!("{text}"),
+        None => println!("{text}"),
+    }
+}
+
+/// Window size showing every CHIP-8 pixel as `SCALING` screen pixels, so
+/// hi-res doubles the window.
+fn window_size(chip8: &CHIP8) -> LogicalSize<f64> {
+    let (width, height) = chip8.display_size();
+    LogicalSize::new(
+        (width as u64 * SCALING) as f64,
+        (height as u64 * SCALING) as f64,
+    )
+}
+
+fn state_slot(keycode: VirtualKeyCode) -> Option<u8> {
+    use VirtualKeyCode::*;
+    [F1, F2, F3, F4, F5, F6, F7, F8, F9]
+        .iter()
+        .position(|&k| k == keycode)
+        .map(|i| i as u8 + 1)
+}
+
+fn state_path(rom_path: &Path, slot: u8) -> PathBuf {
+    rom_path.with_extension(format!("state{slot}"))
+}
+
+/// F1-F9 save to the matching slot, Shift+F1-F9 load from it.
+fn handle_state_slot(
+    chip8: &mut CHIP8,
+    debugger: &mut Option<Box<dyn Debugger>>,
+    rom_path: Option<&Path>,
+    slot: u8,
+    load: bool,
+) -> bool {
+    let Some(rom_path) = rom_path else {
+        let text = "Save states need the ROM to be loaded from a file.".to_owned();
+        report(debugger, text, true);
+        return false;
+    };
+    let path = state_path(rom_path, slot);
+    if load {
+        let loaded = std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|state| chip8.load_state(&state).map_err(|e| e.to_string()));
+        match loaded {
+            Ok(()) => {
+                report(debugger, format!("Loaded state from slot {slot}."), false);
+                true
+            }
+            Err(err) => {
+                let text = format!("Could not load \"{}\": {err}", path.display());
+                report(debugger, text, true);
+                false
+            }
+        }
+    } else {
+        match std::fs::write(&path, chip8.save_state()) {
+            Ok(()) => report(debugger, format!("Saved state to slot {slot}."), false),
+            Err(err) => {
+                let text = format!("Could not write \"{}\": {err}", path.display());
+                report(debugger, text, true);
+            }
+        }
+        false
+    }
+}
+
 pub fn drive(
-    program: &[u8],
-    old_behaviour_conf: OldBehaviourConfig,
-    tick_time: Duration,
+    mut chip8: CHIP8,
+    rom_path: Option<&Path>,
+    mut timing: Timing,
     color_conf: ColorConfig,
+    rewind_depth: usize,
+    mut debugger: Option<Box<dyn Debugger>>,
 ) -> Result<(), Error> {
     let event_loop = EventLoop::new();
 
-    let window = {
-        let size = LogicalSize::new((64 * SCALING) as f64, (32 * SCALING) as f64);
-        WindowBuilder::new()
-            .with_title("CHIP-8 Emulator")
-            .with_inner_size(size)
-            .with_min_inner_size(size)
-            .build(&event_loop)
-            .unwrap()
-    };
+    let window = WindowBuilder::new()
+        .with_title("CHIP-8 Emulator")
+        .with_inner_size(window_size(&chip8))
+        .with_min_inner_size(LogicalSize::new(
+            (DISPLAY_WIDTH as u64 * SCALING) as f64,
+            (DISPLAY_HEIGHT as u64 * SCALING) as f64,
+        ))
+        .build(&event_loop)
+        .unwrap();
 
     let pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(64, 32, surface_texture)?
+        let (width, height) = chip8.display_size();
+        Pixels::new(width as u32, height as u32, surface_texture)?
     };
 
     let mut display = Display(pixels, color_conf);
@@ -50,29 +174,37 @@ pub fn drive(
         b: color_conf.bg_color.2 as f64 / 255.,
         a: 1.0,
     });
-    display.clear_screen();
     let mut cinput = CHIP8Input {
         pressed_keys: [false; 16],
         released_key: None,
     };
-    let mut chip8 = crate::CHIP8::new(old_behaviour_conf);
+    display.draw(&chip8);
 
-    let mut timer_instant = Instant::now();
+    let mut next_frame = Instant::now() + FRAME_TIME;
+    // Set whenever the picture changed, cleared once it is presented.
+    let mut dirty = false;
 
-    let mut loop_speed_limit_instant = Instant::now();
+    let mut halted = false;
+    // Set by breakpoints and the P hotkey. Unlike `halted`, the machine can
+    // carry on from here.
+    let mut paused = false;
 
-    chip8.load_program(program);
-    event_loop.run(move |event, _, control_flow| {
-        if timer_instant.elapsed() >= Duration::from_micros(16667) {
-            chip8.delay_timer = chip8.delay_timer.saturating_sub(1);
-            chip8.sound_timer = chip8.sound_timer.saturating_sub(1);
-            if chip8.sound_timer > 0 {
-                print!("{}", 7u8 as char);
-            }
-            timer_instant = Instant::now();
+    let mut rewind = Rewind::new(rewind_depth);
+    let mut rewinding = false;
+
+    #[cfg(feature = "audio")]
+    let audio = match crate::audio::Audio::new() {
+        Ok(audio) => Some(audio),
+        Err(err) => {
+            report(&mut debugger, format!("Sound is off: {err}."), true);
+            None
         }
+    };
+
+    let rom_path = rom_path.map(Path::to_path_buf);
+    let mut modifiers = ModifiersState::empty();
 
-        cinput.released_key = None;
+    event_loop.run(move |event, _, control_flow| {
         match event {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
@@ -89,13 +221,118 @@ pub fn drive(
                     .resize_surface(new_size.width, new_size.height)
                     .unwrap();
             }
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(state),
+                ..
+            } => {
+                modifiers = state;
+            }
             Event::MainEventsCleared => {
-                //window.request_redraw();
+                let now = Instant::now();
+                if now >= next_frame {
+                    if let Some(debugger) = &mut debugger {
+                        let was_paused = paused;
+                        if !debugger.frame(&mut chip8, &cinput, &mut paused) {
+                            control_flow.set_exit();
+                        }
+                        if was_paused && !paused && !halted {
+                            window.set_title("CHIP-8 Emulator");
+                        }
+                        // Steps change the machine outside of a frame.
+                        dirty = true;
+                    }
+                    if rewinding {
+                        if let Some(state) = rewind.pop() {
+                            match chip8.load_state(&state) {
+                                Ok(()) => {
+                                    halted = false;
+                                    dirty = true;
+                                }
+                                Err(err) => {
+                                    let text = format!("Could not rewind: {err}");
+                                    report(&mut debugger, text, true);
+                                    rewind.clear();
+                                    rewinding = false;
+                                }
+                            }
+                        }
+                    } else if !halted && !paused {
+                        let result = match timing {
+                            Timing::InstructionsPerFrame(n) => chip8.step_frame(&cinput, n),
+                            Timing::Vip => chip8.run_vip_frame(&cinput),
+                        };
+                        // A release is only reported to the frame right after it.
+                        cinput.released_key = None;
+                        match result {
+                            Ok(res) => {
+                                dirty |= res.request_redraw;
+                                for text in res.log {
+                                    report(&mut debugger, text, false);
+                                }
+                                if let Status::Stopped(reason) = res.status {
+                                    let text = format!("Stopped: {reason}. Press P to continue.");
+                                    report(&mut debugger, text, false);
+                                    window
+                                        .set_title(&format!("CHIP-8 Emulator - stopped: {reason}"));
+                                    paused = true;
+                                }
+                                if res.exit_requested {
+                                    report(&mut debugger, "Program exited.".to_owned(), false);
+                                    window.set_title("CHIP-8 Emulator - exited");
+                                    if let Some(debugger) = &mut debugger {
+                                        debugger.halted(true);
+                                    }
+                                    halted = true;
+                                }
+                            }
+                            Err(err) => {
+                                report(&mut debugger, format!("Emulation halted: {err}"), true);
+                                window.set_title(&format!("CHIP-8 Emulator - halted: {err}"));
+                                if let Some(debugger) = &mut debugger {
+                                    debugger.halted(false);
+                                }
+                                halted = true;
+                            }
+                        }
+                        if !halted {
+                            rewind.push(chip8.save_state());
+                        }
+                    }
+
+                    #[cfg(feature = "audio")]
+                    if let Some(audio) = &audio {
+                        // A stopped machine does not count the sound timer
+                        // down, so it would otherwise beep forever.
+                        let mut buzzer = chip8.buzzer();
+                        buzzer.on &= !halted && !paused && !rewinding;
+                        audio.set(buzzer);
+                    }
+                    #[cfg(not(feature = "audio"))]
+                    if chip8.buzzer().on && !halted && !paused && !rewinding {
+                        print!("{}", 7u8 as char);
+                    }
+
+                    next_frame += FRAME_TIME;
+                    if now > next_frame + FRAME_TIME * MAX_FRAME_LAG {
+                        next_frame = now + FRAME_TIME;
+                    }
+                    if dirty {
+                        // Switching between lo-res and hi-res keeps the
+                        // size of a CHIP-8 pixel on screen.
+                        if display.draw(&chip8) {
+                            window.set_inner_size(window_size(&chip8));
+                        }
+                        window.request_redraw();
+                        dirty = false;
+                    }
+                }
+                if !matches!(control_flow, ControlFlow::ExitWithCode(_)) {
+                    control_flow.set_wait_until(next_frame);
+                }
             }
             Event::RedrawRequested(_) => {
                 if let Err(_err) = display.0.render() {
                     control_flow.set_exit();
-                    return;
                 }
             }
             Event::WindowEvent {
@@ -105,8 +342,44 @@ pub fn drive(
                 use winit::event::ElementState;
                 if input.state == ElementState::Pressed {
                     if let Some(keycode) = input.virtual_keycode {
+                        if let Some(slot) = state_slot(keycode) {
+                            let load = modifiers.shift();
+                            if handle_state_slot(
+                                &mut chip8,
+                                &mut debugger,
+                                rom_path.as_deref(),
+                                slot,
+                                load,
+                            ) {
+                                halted = false;
+                                dirty = true;
+                            }
+                        }
                         match keycode {
                             VirtualKeyCode::Escape => control_flow.set_exit(),
+                            VirtualKeyCode::Back => rewinding = true,
+                            VirtualKeyCode::P if !halted => {
+                                paused = !paused;
+                                window.set_title(match paused {
+                                    true => "CHIP-8 Emulator - paused",
+                                    false => "CHIP-8 Emulator",
+                                });
+                            }
+                            VirtualKeyCode::Equals
+                            | VirtualKeyCode::Plus
+                            | VirtualKeyCode::NumpadAdd
+                            | VirtualKeyCode::Minus
+                            | VirtualKeyCode::NumpadSubtract => {
+                                if let Timing::InstructionsPerFrame(ipf) = &mut timing {
+                                    let faster = !matches!(
+                                        keycode,
+                                        VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract
+                                    );
+                                    *ipf = adjust_speed(*ipf, faster);
+                                    let text = format!("Speed: {ipf} instructions per frame.");
+                                    report(&mut debugger, text, false);
+                                }
+                            }
                             VirtualKeyCode::Key1 => {
                                 cinput.pressed_keys[InputKey::D1 as usize] = true;
                             }
@@ -162,6 +435,7 @@ pub fn drive(
                     if let Some(keycode) = input.virtual_keycode {
                         match keycode {
                             VirtualKeyCode::Escape => control_flow.set_exit(),
+                            VirtualKeyCode::Back => rewinding = false,
                             VirtualKeyCode::Key1 => {
                                 cinput.pressed_keys[InputKey::D1 as usize] = false;
                                 cinput.released_key.replace(InputKey::D1);
@@ -233,13 +507,5 @@ pub fn drive(
             }
             _ => (),
         };
-
-        if loop_speed_limit_instant.elapsed() >= tick_time {
-            let res = chip8.update(cinput.clone(), &mut display);
-            if res.request_redraw {
-                window.request_redraw();
-            }
-            loop_speed_limit_instant = Instant::now();
-        }
     })
 }
@@ -0,0 +1,728 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+const ORIGIN: u16 = 0x200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+            let len = rest[start..]
+                .find(char::is_whitespace)
+                .unwrap_or(rest.len() - start);
+            tokens.push(Token {
+                text: &rest[start..start + len],
+                line: line_idx + 1,
+                column: line[..line.len() - rest.len() + start].chars().count() + 1,
+            });
+            rest = &rest[start + len..];
+        }
+    }
+    tokens
+}
+
+/// A skip condition as written after `if` or `while`.
+#[derive(Debug, Clone, Copy)]
+enum Condition {
+    EqImm(u8, u8),
+    NeImm(u8, u8),
+    EqReg(u8, u8),
+    NeReg(u8, u8),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Condition {
+    fn negate(self) -> Self {
+        use Condition::*;
+        match self {
+            EqImm(x, nn) => NeImm(x, nn),
+            NeImm(x, nn) => EqImm(x, nn),
+            EqReg(x, y) => NeReg(x, y),
+            NeReg(x, y) => EqReg(x, y),
+            Key(x) => NotKey(x),
+            NotKey(x) => Key(x),
+        }
+    }
+
+    /// The instruction that skips the next one when the condition holds.
+    fn skip_if(self) -> Instruction {
+        match self {
+            Condition::EqImm(x, nn) => Instruction::SeImm(x, nn),
+            Condition::NeImm(x, nn) => Instruction::SneImm(x, nn),
+            Condition::EqReg(x, y) => Instruction::SeReg(x, y),
+            Condition::NeReg(x, y) => Instruction::SneReg(x, y),
+            Condition::Key(x) => Instruction::Skp(x),
+            Condition::NotKey(x) => Instruction::Sknp(x),
+        }
+    }
+}
+
+enum Block<'a> {
+    If {
+        token: Token<'a>,
+        jump: usize,
+        has_else: bool,
+    },
+    Loop {
+        token: Token<'a>,
+        start: u16,
+        breaks: Vec<usize>,
+    },
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    rom: Vec<u8>,
+    labels: HashMap<&'a str, u16>,
+    consts: HashMap<&'a str, u16>,
+    aliases: HashMap<&'a str, u8>,
+    /// Offsets of 12-bit address operands waiting for a label definition.
+    fixups: Vec<(usize, Token<'a>)>,
+    /// Offsets of the 16-bit operands of `i := long`.
+    long_fixups: Vec<(usize, Token<'a>)>,
+    blocks: Vec<Block<'a>>,
+}
+
+impl<'a> Assembler<'a> {
+    fn here(&self) -> u16 {
+        ORIGIN.wrapping_add(self.rom.len() as u16)
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, after: &Token<'a>) -> Result<Token<'a>, AsmError> {
+        self.next()
+            .ok_or_else(|| after.error(format!("unexpected end of input after '{}'", after.text)))
+    }
+
+    fn expect_text(&mut self, after: &Token<'a>, text: &str) -> Result<(), AsmError> {
+        let token = self.expect(after)?;
+        if token.text != text {
+            return Err(token.error(format!("expected '{text}', found '{}'", token.text)));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.rom.extend_from_slice(&instruction.to_bytes());
+    }
+
+    fn peek_text(&self, text: &str) -> bool {
+        self.tokens
+            .get(self.pos)
+            .is_some_and(|token| token.text == text)
+    }
+
+    /// Parses `vx` or the XO-CHIP range `vx - vy`.
+    fn register_range(&mut self, after: &Token<'a>) -> Result<(u8, Option<u8>), AsmError> {
+        let x = self.expect_register(after)?;
+        if !self.peek_text("-") {
+            return Ok((x, None));
+        }
+        let dash = self.expect(after)?;
+        Ok((x, Some(self.expect_register(&dash)?)))
+    }
+
+    /// Emits an instruction whose 12-bit operand is `target`, which may be a
+    /// label defined later.
+    fn emit_addressed(
+        &mut self,
+        make: fn(u16) -> Instruction,
+        target: Token<'a>,
+    ) -> Result<(), AsmError> {
+        match self.value(&target)? {
+            Some(address) => {
+                if address > 0xFFF {
+                    return Err(target.error(format!("address {address:#X} out of range")));
+                }
+                self.emit(make(address));
+            }
+            None => {
+                self.emit(make(0));
+                self.fixups.push((self.rom.len() - 2, target));
+            }
+        }
+        Ok(())
+    }
+
+    fn register(&self, token: &Token<'a>) -> Option<u8> {
+        if let Some(&r) = self.aliases.get(token.text) {
+            return Some(r);
+        }
+        let digits = token.text.strip_prefix(['v', 'V'])?;
+        if digits.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digits, 16).ok()
+    }
+
+    fn expect_register(&mut self, after: &Token<'a>) -> Result<u8, AsmError> {
+        let token = self.expect(after)?;
+        self.register(&token)
+            .ok_or_else(|| token.error(format!("expected a register, found '{}'", token.text)))
+    }
+
+    fn number(text: &str) -> Option<i32> {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let value = if let Some(hex) = text.strip_prefix("0x") {
+            i32::from_str_radix(hex, 16).ok()?
+        } else if let Some(bin) = text.strip_prefix("0b") {
+            i32::from_str_radix(bin, 2).ok()?
+        } else {
+            text.parse::<i32>().ok()?
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    /// Resolves a number, constant or already defined label. `None` means
+    /// the token may be a label that is not defined yet.
+    fn value(&self, token: &Token<'a>) -> Result<Option<u16>, AsmError> {
+        if let Some(n) = Self::number(token.text) {
+            if !(-128..=0xFFFF).contains(&n) {
+                return Err(token.error(format!("number {} out of range", token.text)));
+            }
+            return Ok(Some(n as u16));
+        }
+        if let Some(&value) = self.consts.get(token.text) {
+            return Ok(Some(value));
+        }
+        if let Some(&address) = self.labels.get(token.text) {
+            return Ok(Some(address));
+        }
+        if self.register(token).is_some() || !is_identifier(token.text) {
+            return Err(token.error(format!("expected a value, found '{}'", token.text)));
+        }
+        Ok(None)
+    }
+
+    fn byte_value(&mut self, after: &Token<'a>) -> Result<u8, AsmError> {
+        let token = self.expect(after)?;
+        self.byte_of(&token)
+    }
+
+    fn byte_of(&self, token: &Token<'a>) -> Result<u8, AsmError> {
+        match self.value(token)? {
+            Some(value) if value <= 0xFF || value >= 0xFF80 => Ok(value as u8),
+            Some(_) => Err(token.error(format!("'{}' does not fit in a byte", token.text))),
+            None => Err(token.error(format!("undefined constant '{}'", token.text))),
+        }
+    }
+
+    fn condition(&mut self, after: &Token<'a>) -> Result<Condition, AsmError> {
+        let x = self.expect_register(after)?;
+        let op = self.expect(after)?;
+        match op.text {
+            "key" => return Ok(Condition::Key(x)),
+            "-key" => return Ok(Condition::NotKey(x)),
+            "==" | "!=" => {}
+            _ => return Err(op.error(format!("unsupported condition '{}'", op.text))),
+        }
+        let rhs = self.expect(&op)?;
+        Ok(match (op.text, self.register(&rhs)) {
+            ("==", Some(y)) => Condition::EqReg(x, y),
+            ("!=", Some(y)) => Condition::NeReg(x, y),
+            ("==", None) => Condition::EqImm(x, self.byte_of(&rhs)?),
+            _ => Condition::NeImm(x, self.byte_of(&rhs)?),
+        })
+    }
+
+    fn register_statement(&mut self, token: Token<'a>, x: u8) -> Result<(), AsmError> {
+        let op = self.expect(&token)?;
+        let rhs = self.expect(&op)?;
+        let y = self.register(&rhs);
+        let instruction = match (op.text, y) {
+            (":=", Some(y)) => Instruction::LdReg(x, y),
+            (":=", None) => match rhs.text {
+                "random" => Instruction::Rnd(x, self.byte_value(&rhs)?),
+                "key" => Instruction::LdVxK(x),
+                "delay" => Instruction::LdVxDt(x),
+                _ => Instruction::LdImm(x, self.byte_of(&rhs)?),
+            },
+            ("+=", Some(y)) => Instruction::AddReg(x, y),
+            ("+=", None) => Instruction::AddImm(x, self.byte_of(&rhs)?),
+            ("-=", Some(y)) => Instruction::Sub(x, y),
+            ("-=", None) => Instruction::AddImm(x, self.byte_of(&rhs)?.wrapping_neg()),
+            ("=-", Some(y)) => Instruction::Subn(x, y),
+            ("|=", Some(y)) => Instruction::Or(x, y),
+            ("&=", Some(y)) => Instruction::And(x, y),
+            ("^=", Some(y)) => Instruction::Xor(x, y),
+            (">>=", Some(y)) => Instruction::Shr(x, y),
+            ("<<=", Some(y)) => Instruction::Shl(x, y),
+            ("=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", None) => {
+                return Err(rhs.error(format!("expected a register, found '{}'", rhs.text)))
+            }
+            _ => return Err(op.error(format!("unknown operator '{}'", op.text))),
+        };
+        self.emit(instruction);
+        Ok(())
+    }
+
+    fn patch(&mut self, offset: usize, address: u16) {
+        let opcode = u16::from_be_bytes([self.rom[offset], self.rom[offset + 1]]);
+        let patched = (opcode & 0xF000) | (address & 0x0FFF);
+        self.rom[offset..offset + 2].copy_from_slice(&patched.to_be_bytes());
+    }
+
+    fn statement(&mut self, token: Token<'a>) -> Result<(), AsmError> {
+        if let Some(x) = self.register(&token) {
+            return self.register_statement(token, x);
+        }
+        match token.text {
+            ":" => {
+                let name = self.expect(&token)?;
+                if !is_identifier(name.text) {
+                    return Err(name.error(format!("invalid label name '{}'", name.text)));
+                }
+                if self.labels.insert(name.text, self.here()).is_some() {
+                    return Err(name.error(format!("label '{}' defined twice", name.text)));
+                }
+            }
+            ":const" => {
+                let name = self.expect(&token)?;
+                let value = self.expect(&name)?;
+                match self.value(&value)? {
+                    Some(v) => {
+                        self.consts.insert(name.text, v);
+                    }
+                    None => return Err(value.error(format!("undefined constant '{}'", value.text))),
+                }
+            }
+            ":alias" => {
+                let name = self.expect(&token)?;
+                let register = self.expect_register(&name)?;
+                self.aliases.insert(name.text, register);
+            }
+            "clear" => self.emit(Instruction::Cls),
+            "scroll-down" => {
+                let n = self.expect(&token)?;
+                let rows = self.byte_of(&n)?;
+                if rows > 0xF {
+                    return Err(n.error("scroll amount must be between 0 and 15"));
+                }
+                self.emit(Instruction::ScrollDown(rows));
+            }
+            "scroll-up" => {
+                let n = self.expect(&token)?;
+                let rows = self.byte_of(&n)?;
+                if rows > 0xF {
+                    return Err(n.error("scroll amount must be between 0 and 15"));
+                }
+                self.emit(Instruction::ScrollUp(rows));
+            }
+            "scroll-right" => self.emit(Instruction::ScrollRight),
+            "scroll-left" => self.emit(Instruction::ScrollLeft),
+            "exit" => self.emit(Instruction::Exit),
+            "lores" => self.emit(Instruction::Low),
+            "hires" => self.emit(Instruction::High),
+            "plane" => {
+                let n = self.expect(&token)?;
+                let mask = self.byte_of(&n)?;
+                if mask > 3 {
+                    return Err(n.error("plane mask must be between 0 and 3"));
+                }
+                self.emit(Instruction::Plane(mask));
+            }
+            "audio" => self.emit(Instruction::Audio),
+            "pitch" => {
+                self.expect_text(&token, ":=")?;
+                let x = self.expect_register(&token)?;
+                self.emit(Instruction::Pitch(x));
+            }
+            "return" | ";" => self.emit(Instruction::Ret),
+            "jump" => {
+                let target = self.expect(&token)?;
+                self.emit_addressed(Instruction::Jp, target)?;
+            }
+            "jump0" => {
+                let target = self.expect(&token)?;
+                self.emit_addressed(Instruction::JpV0, target)?;
+            }
+            ":call" => {
+                let target = self.expect(&token)?;
+                self.emit_addressed(Instruction::Call, target)?;
+            }
+            "i" => {
+                let op = self.expect(&token)?;
+                let rhs = self.expect(&op)?;
+                match op.text {
+                    ":=" if rhs.text == "hex" => {
+                        let x = self.expect_register(&rhs)?;
+                        self.emit(Instruction::LdF(x));
+                    }
+                    ":=" if rhs.text == "bighex" => {
+                        let x = self.expect_register(&rhs)?;
+                        self.emit(Instruction::LdHf(x));
+                    }
+                    ":=" if rhs.text == "long" => {
+                        let target = self.expect(&rhs)?;
+                        match self.value(&target)? {
+                            Some(address) => self.emit(Instruction::LdILong(address)),
+                            None => {
+                                self.emit(Instruction::LdILong(0));
+                                self.long_fixups.push((self.rom.len() - 2, target));
+                            }
+                        }
+                    }
+                    ":=" => self.emit_addressed(Instruction::LdI, rhs)?,
+                    "+=" => match self.register(&rhs) {
+                        Some(x) => self.emit(Instruction::AddI(x)),
+                        None => {
+                            return Err(
+                                rhs.error(format!("expected a register, found '{}'", rhs.text))
+                            )
+                        }
+                    },
+                    _ => return Err(op.error(format!("unknown operator '{}'", op.text))),
+                }
+            }
+            "delay" | "buzzer" => {
+                self.expect_text(&token, ":=")?;
+                let x = self.expect_register(&token)?;
+                self.emit(if token.text == "delay" {
+                    Instruction::LdDtVx(x)
+                } else {
+                    Instruction::LdStVx(x)
+                });
+            }
+            "bcd" => {
+                let x = self.expect_register(&token)?;
+                self.emit(Instruction::LdB(x));
+            }
+            "save" => match self.register_range(&token)? {
+                (x, None) => self.emit(Instruction::LdIVx(x)),
+                (x, Some(y)) => self.emit(Instruction::SaveRange(x, y)),
+            },
+            "load" => match self.register_range(&token)? {
+                (x, None) => self.emit(Instruction::LdVxI(x)),
+                (x, Some(y)) => self.emit(Instruction::LoadRange(x, y)),
+            },
+            "saveflags" => {
+                let x = self.expect_register(&token)?;
+                self.emit(Instruction::LdRVx(x));
+            }
+            "loadflags" => {
+                let x = self.expect_register(&token)?;
+                self.emit(Instruction::LdVxR(x));
+            }
+            "sprite" => {
+                let x = self.expect_register(&token)?;
+                let y = self.expect_register(&token)?;
+                let height = self.expect(&token)?;
+                let n = self.byte_of(&height)?;
+                if n > 0xF {
+                    return Err(height.error("sprite height must be between 0 and 15"));
+                }
+                self.emit(Instruction::Drw(x, y, n));
+            }
+            "if" => {
+                let condition = self.condition(&token)?;
+                let keyword = self.expect(&token)?;
+                match keyword.text {
+                    "then" => self.emit(condition.negate().skip_if()),
+                    "begin" => {
+                        self.emit(condition.skip_if());
+                        self.emit(Instruction::Jp(0));
+                        self.blocks.push(Block::If {
+                            token,
+                            jump: self.rom.len() - 2,
+                            has_else: false,
+                        });
+                    }
+                    _ => {
+                        return Err(keyword.error(format!(
+                            "expected 'then' or 'begin', found '{}'",
+                            keyword.text
+                        )))
+                    }
+                }
+            }
+            "else" => {
+                let jump = match self.blocks.last() {
+                    Some(Block::If {
+                        jump,
+                        has_else: false,
+                        ..
+                    }) => *jump,
+                    _ => return Err(token.error("'else' without 'if ... begin'")),
+                };
+                self.emit(Instruction::Jp(0));
+                let end_jump = self.rom.len() - 2;
+                let here = self.here();
+                self.patch(jump, here);
+                if let Some(Block::If { jump, has_else, .. }) = self.blocks.last_mut() {
+                    *jump = end_jump;
+                    *has_else = true;
+                }
+            }
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let here = self.here();
+                    self.patch(jump, here);
+                }
+                Some(block @ Block::Loop { .. }) => {
+                    self.blocks.push(block);
+                    return Err(token.error("'end' inside 'loop', expected 'again'"));
+                }
+                None => return Err(token.error("'end' without 'if ... begin'")),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                token,
+                start: self.here(),
+                breaks: Vec::new(),
+            }),
+            "while" => {
+                let condition = self.condition(&token)?;
+                if !self.blocks.iter().any(|b| matches!(b, Block::Loop { .. })) {
+                    return Err(token.error("'while' outside of 'loop'"));
+                }
+                self.emit(condition.skip_if());
+                self.emit(Instruction::Jp(0));
+                let offset = self.rom.len() - 2;
+                let Some(Block::Loop { breaks, .. }) = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|b| matches!(b, Block::Loop { .. }))
+                else {
+                    unreachable!()
+                };
+                breaks.push(offset);
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    self.emit(Instruction::Jp(start));
+                    let here = self.here();
+                    for offset in breaks {
+                        self.patch(offset, here);
+                    }
+                }
+                Some(block @ Block::If { .. }) => {
+                    self.blocks.push(block);
+                    return Err(token.error("'again' inside 'if ... begin', expected 'end'"));
+                }
+                None => return Err(token.error("'again' without 'loop'")),
+            },
+            _ if Self::number(token.text).is_some() || self.consts.contains_key(token.text) => {
+                let byte = self.byte_of(&token)?;
+                self.rom.push(byte);
+            }
+            _ => self.emit_addressed(Instruction::Call, token)?,
+        }
+        Ok(())
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Assembles a program written in a subset of Octo into a ROM image meant
+/// to be loaded at 0x200.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler {
+        tokens: tokenize(source),
+        pos: 0,
+        rom: Vec::new(),
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        long_fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+
+    // Programs start at `main`. Unless it is the very first thing, begin
+    // with a jump to it.
+    if let [first, second, ..] = asm.tokens[..] {
+        if !(first.text == ":" && second.text == "main") {
+            asm.emit(Instruction::Jp(0));
+            asm.fixups.push((
+                0,
+                Token {
+                    text: "main",
+                    ..first
+                },
+            ));
+        }
+    }
+
+    while let Some(token) = asm.next() {
+        asm.statement(token)?;
+    }
+
+    if let Some(block) = asm.blocks.last() {
+        return Err(match block {
+            Block::If { token, .. } => token.error("'if ... begin' without 'end'"),
+            Block::Loop { token, .. } => token.error("'loop' without 'again'"),
+        });
+    }
+    if !asm.labels.contains_key("main") {
+        return Err(AsmError {
+            line: 1,
+            column: 1,
+            message: "program has no ': main' label".into(),
+        });
+    }
+    for (offset, token) in std::mem::take(&mut asm.fixups) {
+        let Some(&address) = asm.labels.get(token.text) else {
+            return Err(token.error(format!("undefined label '{}'", token.text)));
+        };
+        if address > 0xFFF {
+            return Err(token.error(format!("label '{}' is out of 12-bit range", token.text)));
+        }
+        asm.patch(offset, address);
+    }
+    for (offset, token) in std::mem::take(&mut asm.long_fixups) {
+        let Some(&address) = asm.labels.get(token.text) else {
+            return Err(token.error(format!("undefined label '{}'", token.text)));
+        };
+        asm.rom[offset..offset + 2].copy_from_slice(&address.to_be_bytes());
+    }
+    // `CHIP8::load_program` refuses odd-length ROMs, so a program ending
+    // in a lone data byte gets a zero after it.
+    if !asm.rom.len().is_multiple_of(2) {
+        asm.rom.push(0);
+    }
+    // The full XO-CHIP address space; anything past 0xFFF can only be
+    // reached with `i := long`.
+    if asm.rom.len() > 0x10000 - ORIGIN as usize {
+        return Err(AsmError {
+            line: 1,
+            column: 1,
+            message: format!(
+                "program is {} bytes, which does not fit in memory",
+                asm.rom.len()
+            ),
+        });
+    }
+    Ok(asm.rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CHIP8Input, Platform, Quirks, CHIP8};
+
+    fn run(source: &str, until: impl FnMut(&CHIP8) -> bool) -> CHIP8 {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8.load_program(&assemble(source).unwrap()).unwrap();
+        let ran = chip8.run_until(&CHIP8Input::default(), 1000, until);
+        assert!(ran.unwrap().is_some(), "{source}");
+        chip8
+    }
+
+    #[test]
+    fn assembled_programs_draw() {
+        let source = "
+            : main
+                v0 := 0xA
+                i := hex v0
+                v1 := 2
+                v2 := 3
+                sprite v1 v2 5
+            : halt
+                jump halt
+        ";
+        let chip8 = run(source, |chip8| chip8.pc() == 0x20A);
+        let glyph = &chip8.memory()[chip8.i_reg() as usize..][..5];
+        assert!(glyph.iter().any(|&row| row != 0));
+        for (y, row) in glyph.iter().enumerate() {
+            for x in 0..8 {
+                assert_eq!(chip8.pixel(2 + x, 3 + y), row >> (7 - x) & 1, "({x}, {y})");
+            }
+        }
+        assert_eq!(chip8.pixel(1, 3) | chip8.pixel(2, 2), 0);
+    }
+
+    #[test]
+    fn control_flow_constants_and_aliases() {
+        let source = "
+            :alias counter v3
+            :const LIMIT 5
+            : main
+                loop
+                    counter += 1
+                    if counter == LIMIT then v4 := 1
+                    while counter != LIMIT
+                again
+                if v4 == 1 begin
+                    v5 := 0x42
+                else
+                    v5 := 0x24
+                end
+            : done
+                jump done
+        ";
+        let chip8 = run(source, |chip8| chip8.registers()[5] != 0);
+        assert_eq!(chip8.registers()[3..6], [5, 1, 0x42]);
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        let err = assemble(": main\n    jump nowhere\n").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 10));
+        assert_eq!(err.message, "undefined label 'nowhere'");
+
+        let err = assemble(": main\n  if v0 == 1 begin\n").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+        assert!(assemble("clear").is_err());
+        assert!(assemble(": main\n: main").is_err());
+        assert!(assemble(": main\n  v0 := 0x100").is_err());
+    }
+
+    #[test]
+    fn odd_output_is_padded_to_whole_instructions() {
+        let rom = assemble(": main\n    exit\n    0x12 0x34 0x56\n").unwrap();
+        assert_eq!(rom, [0x00, 0xFD, 0x12, 0x34, 0x56, 0x00]);
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+
+use crate::Buzzer;
+
+/// Plays the buzzer on the default output device. The frontend hands over
+/// the machine's `Buzzer` once per frame.
+pub struct Audio {
+    buzzer: Arc<Mutex<Buzzer>>,
+    _stream: Stream,
+}
+
+impl Audio {
+    pub fn new() -> Result<Audio, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no audio output device")?;
+        let supported = device
+            .default_output_config()
+            .map_err(|err| err.to_string())?;
+        let config = supported.config();
+        let buzzer = Arc::new(Mutex::new(Buzzer::default()));
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build::<f32>(&device, &config, buzzer.clone()),
+            SampleFormat::I16 => build::<i16>(&device, &config, buzzer.clone()),
+            SampleFormat::U16 => build::<u16>(&device, &config, buzzer.clone()),
+            format => return Err(format!("unsupported sample format {format}")),
+        }
+        .map_err(|err| err.to_string())?;
+        stream.play().map_err(|err| err.to_string())?;
+        Ok(Audio {
+            buzzer,
+            _stream: stream,
+        })
+    }
+
+    pub fn set(&self, buzzer: Buzzer) {
+        *self.buzzer.lock().unwrap() = buzzer;
+    }
+}
+
+fn build<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    buzzer: Arc<Mutex<Buzzer>>,
+) -> Result<Stream, cpal::BuildStreamError> {
+    let sample_rate = config.sample_rate.0;
+    let channels = config.channels as usize;
+    let mut phase = 0.0;
+    let mut mono = Vec::new();
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            mono.resize(data.len() / channels, 0.0);
+            let buzzer = *buzzer.lock().unwrap();
+            buzzer.render(&mut mono, sample_rate, &mut phase);
+            for (frame, &sample) in data.chunks_mut(channels).zip(&mono) {
+                frame.fill(T::from_sample(sample));
+            }
+        },
+        |err| eprintln!("Audio error: {err}"),
+        None,
+    )
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::{Profile, Quirks};
+
+/// Bundled settings for known ROMs, see the header of the file for the
+/// format.
+const DATABASE: &str = include_str!("database.txt");
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub title: Option<&'static str>,
+    pub profile: Option<Profile>,
+    pub instructions_per_frame: Option<usize>,
+    /// Quirk overrides by their command line name.
+    pub quirks: Vec<(&'static str, bool)>,
+    /// Colours for unset pixels, plane 1, plane 2 and both planes.
+    pub palette: Vec<(u8, u8, u8)>,
+    pub keys: Option<&'static str>,
+}
+
+impl Entry {
+    pub fn apply_quirks(&self, quirks: &mut Quirks) {
+        for &(name, value) in &self.quirks {
+            *quirks.get_mut(name).unwrap() = value;
+        }
+    }
+}
+
+pub fn sha1(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+/// Looks the ROM up by its SHA-1. The database is parsed on the first
+/// call; the tests make sure every bundled entry parses.
+pub fn lookup(rom: &[u8]) -> Option<Entry> {
+    static ENTRIES: OnceLock<HashMap<&'static str, Entry>> = OnceLock::new();
+    ENTRIES
+        .get_or_init(|| parse(DATABASE).expect("the bundled ROM database is malformed"))
+        .get(sha1(rom).as_str())
+        .cloned()
+}
+
+/// Every entry of the database by the hash it is filed under, or the first
+/// malformed line.
+fn parse(database: &'static str) -> Result<HashMap<&'static str, Entry>, String> {
+    let mut entries = HashMap::new();
+    let mut current = None;
+    for (number, line) in database.lines().map(str::trim).enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = |why: &str| format!("line {}: {why}: \"{line}\"", number + 1);
+        if let Some(hash) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            if hash.len() != 40 || !hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+                return Err(bad("not a lowercase SHA-1"));
+            }
+            if entries.insert(hash, Entry::default()).is_some() {
+                return Err(bad("duplicate ROM"));
+            }
+            current = Some(hash);
+            continue;
+        }
+        let Some(entry) = current.and_then(|hash| entries.get_mut(hash)) else {
+            return Err(bad("setting before the first ROM"));
+        };
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(bad("expected key = value"));
+        };
+        let value = value.trim();
+        match key.trim() {
+            "title" => entry.title = Some(value),
+            "platform" => {
+                entry.profile =
+                    Some(Profile::from_name(value).ok_or_else(|| bad("unknown platform"))?)
+            }
+            "ipf" => {
+                entry.instructions_per_frame = Some(value.parse().map_err(|_| bad("bad number"))?)
+            }
+            "quirks" => {
+                for setting in value.split_whitespace() {
+                    let quirk = match setting.split_once('=') {
+                        Some((name, "on")) => (name, true),
+                        Some((name, "off")) => (name, false),
+                        _ => return Err(bad("expected NAME=on or NAME=off")),
+                    };
+                    if Quirks::default().get_mut(quirk.0).is_none() {
+                        return Err(bad("unknown quirk"));
+                    }
+                    entry.quirks.push(quirk);
+                }
+            }
+            "palette" => {
+                for color in value.split_whitespace() {
+                    let rgb = match u32::from_str_radix(color, 16) {
+                        Ok(rgb) if color.len() == 6 => rgb,
+                        _ => return Err(bad("expected RRGGBB colours")),
+                    };
+                    entry
+                        .palette
+                        .push(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+                }
+            }
+            "keys" => entry.keys = Some(value),
+            _ => return Err(bad("unknown key")),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_bundled_entry_parses() {
+        let entries = parse(DATABASE).unwrap();
+        assert!(!entries.is_empty());
+        for (hash, entry) in &entries {
+            assert!(entry.profile.is_some(), "{hash} has no platform");
+        }
+    }
+
+    #[test]
+    fn bundled_samples_are_found() {
+        let entry = lookup(include_bytes!("../sample/br8kout.ch8")).unwrap();
+        assert_eq!(entry.palette, [(0x28, 0x28, 0x28), (0xeb, 0xdb, 0xb2)]);
+        assert_eq!(lookup(&[0x12, 0x00]), None);
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        assert!(parse("title = Orphan").is_err());
+        assert!(parse("[not a hash]").is_err());
+        for database in [
+            "[0123456789abcdef0123456789abcdef01234567]\n[0123456789abcdef0123456789abcdef01234567]",
+            "[0123456789abcdef0123456789abcdef01234567]\nipf = fast",
+            "[0123456789abcdef0123456789abcdef01234567]\nplatform = NES",
+            "[0123456789abcdef0123456789abcdef01234567]\nquirks = WRAP",
+            "[0123456789abcdef0123456789abcdef01234567]\npalette = FFF",
+            "[0123456789abcdef0123456789abcdef01234567]\ncolour = red",
+        ] {
+            let err = parse(database).unwrap_err();
+            assert!(err.starts_with("line 2:"), "{err}");
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::expr::{Expr, Template};
+
+/// An opcode with wildcards, such as `00E0` or `DXYN`. Hex digits have to
+/// match, any other character matches any nibble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    value: u16,
+    mask: u16,
+}
+
+impl OpcodePattern {
+    pub fn parse(pattern: &str) -> Option<OpcodePattern> {
+        if pattern.chars().count() != 4 {
+            return None;
+        }
+        let (mut value, mut mask) = (0, 0);
+        for c in pattern.chars() {
+            value <<= 4;
+            mask <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                value |= digit as u16;
+                mask |= 0xF;
+            }
+        }
+        Some(OpcodePattern { value, mask })
+    }
+
+    pub fn matches(self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for shift in [12, 8, 4, 0] {
+            match self.mask >> shift & 0xF {
+                0 => write!(f, "?")?,
+                _ => write!(f, "{:X}", self.value >> shift & 0xF)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{x:X}"),
+            Register::I => write!(f, "I"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watchpoint {
+    Memory {
+        range: RangeInclusive<usize>,
+        access: Access,
+    },
+    /// Triggers when an instruction changes the register's value.
+    Register(Register),
+}
+
+impl Watchpoint {
+    /// Reads `I`, `VX`, or a memory address or `start-end` range with an
+    /// optional `:r` or `:w` suffix to only watch reads or writes.
+    pub fn parse(watch: &str) -> Option<Watchpoint> {
+        let upper = watch.to_uppercase();
+        if upper == "I" {
+            return Some(Watchpoint::Register(Register::I));
+        }
+        if let Some(x) = upper.strip_prefix('V').filter(|x| x.len() == 1) {
+            return u8::from_str_radix(x, 16)
+                .ok()
+                .map(|x| Watchpoint::Register(Register::V(x)));
+        }
+        let (range, access) = match watch.rsplit_once(':') {
+            Some((range, "r")) => (range, Access::Read),
+            Some((range, "w")) => (range, Access::Write),
+            Some((range, "rw")) => (range, Access::Any),
+            Some(_) => return None,
+            None => (watch, Access::Any),
+        };
+        let range = match range.split_once('-') {
+            Some((start, end)) => parse_number(start)?..=parse_number(end)?,
+            None => parse_number(range)?..=parse_number(range)?,
+        };
+        Some(Watchpoint::Memory { range, access })
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Memory { range, access } => {
+                write!(f, "{:#05X}", range.start())?;
+                if range.end() != range.start() {
+                    write!(f, "-{:#05X}", range.end())?;
+                }
+                match access {
+                    Access::Read => write!(f, ":r"),
+                    Access::Write => write!(f, ":w"),
+                    Access::Any => Ok(()),
+                }
+            }
+            Watchpoint::Register(register) => write!(f, "{register}"),
+        }
+    }
+}
+
+/// Reads a decimal or 0x-prefixed hexadecimal number.
+pub fn parse_number(string: &str) -> Option<usize> {
+    let string = string.trim().to_lowercase();
+    match string.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => string.parse().ok(),
+    }
+}
+
+/// An expression checked before every instruction, which either stops
+/// execution or, as a log point, reports a message when it holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub source: String,
+    pub expr: Expr,
+    pub log: Option<Template>,
+}
+
+impl Condition {
+    pub fn new(source: &str, log: Option<&str>) -> Result<Condition, String> {
+        Ok(Condition {
+            source: source.trim().to_owned(),
+            expr: Expr::parse(source)?,
+            log: log.map(Template::parse).transpose()?,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.log {
+            Some(_) => write!(f, "log point {}", self.source),
+            None => write!(f, "condition {}", self.source),
+        }
+    }
+}
+
+/// Breakpoints and watchpoints checked by `CHIP8::update`.
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    pub addresses: BTreeSet<u16>,
+    pub opcodes: Vec<OpcodePattern>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub conditions: Vec<Condition>,
+    /// How many times execution reached each address. Counting starts when
+    /// the first condition is added, as only conditions read it.
+    hits: Vec<u32>,
+}
+
+impl Breakpoints {
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+            && self.opcodes.is_empty()
+            && self.watchpoints.is_empty()
+            && self.conditions.is_empty()
+    }
+
+    /// Counts another arrival at `pc`, returning the new count.
+    pub(crate) fn hit(&mut self, pc: usize) -> u32 {
+        if self.hits.len() <= pc {
+            self.hits.resize(pc + 1, 0);
+        }
+        self.hits[pc] += 1;
+        self.hits[pc]
+    }
+
+    pub(crate) fn check_instruction(&self, pc: u16, opcode: u16) -> Option<StopReason> {
+        if self.addresses.contains(&pc) {
+            return Some(StopReason::Breakpoint { pc });
+        }
+        self.opcodes
+            .iter()
+            .any(|pattern| pattern.matches(opcode))
+            .then_some(StopReason::Opcode { pc, opcode })
+    }
+
+    pub(crate) fn check_memory(&self, pc: u16, index: usize, write: bool) -> Option<StopReason> {
+        let hit = self.watchpoints.iter().any(|watchpoint| match watchpoint {
+            Watchpoint::Memory { range, access } => {
+                range.contains(&index)
+                    && match access {
+                        Access::Read => !write,
+                        Access::Write => write,
+                        Access::Any => true,
+                    }
+            }
+            Watchpoint::Register(_) => false,
+        });
+        match (hit, write) {
+            (false, _) => None,
+            (true, false) => Some(StopReason::MemoryRead { pc, index }),
+            (true, true) => Some(StopReason::MemoryWrite { pc, index }),
+        }
+    }
+
+    /// Compares the registers from before an instruction with those after.
+    pub(crate) fn check_registers(
+        &self,
+        pc: u16,
+        before: (u16, [u8; 16]),
+        after: (u16, [u8; 16]),
+    ) -> Option<StopReason> {
+        self.watchpoints.iter().find_map(|watchpoint| {
+            let (register, old, new) = match *watchpoint {
+                Watchpoint::Register(Register::I) => (Register::I, before.0, after.0),
+                Watchpoint::Register(Register::V(x)) => (
+                    Register::V(x),
+                    before.1[x as usize & 0xF] as u16,
+                    after.1[x as usize & 0xF] as u16,
+                ),
+                Watchpoint::Memory { .. } => return None,
+            };
+            (old != new).then_some(StopReason::Register {
+                pc,
+                register,
+                old,
+                new,
+            })
+        })
+    }
+}
+
+/// Why `CHIP8::update` stopped. `pc` is the address of the instruction that
+/// hit the breakpoint, which for watchpoints has already executed and for
+/// everything else is about to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint {
+        pc: u16,
+    },
+    Opcode {
+        pc: u16,
+        opcode: u16,
+    },
+    MemoryRead {
+        pc: u16,
+        index: usize,
+    },
+    MemoryWrite {
+        pc: u16,
+        index: usize,
+    },
+    Register {
+        pc: u16,
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+    Condition {
+        pc: u16,
+        source: String,
+    },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint { pc } => write!(f, "breakpoint at {pc:#05X}"),
+            StopReason::Opcode { pc, opcode } => write!(f, "opcode {opcode:04X} at {pc:#05X}"),
+            StopReason::MemoryRead { pc, index } => {
+                write!(f, "read of {index:#05X} by {pc:#05X}")
+            }
+            StopReason::MemoryWrite { pc, index } => {
+                write!(f, "write to {index:#05X} by {pc:#05X}")
+            }
+            StopReason::Register {
+                pc,
+                register,
+                old,
+                new,
+            } => write!(
+                f,
+                "{register} changed from {old:#X} to {new:#X} by {pc:#05X}"
+            ),
+            StopReason::Condition { pc, source } => {
+                write!(f, "condition {source} held at {pc:#05X}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Status {
+    #[default]
+    Running,
+    Stopped(StopReason),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opcode_patterns_match_hex_digits_and_wildcards() {
+        let pattern = OpcodePattern::parse("DXYN").unwrap();
+        assert!(pattern.matches(0xD015));
+        assert!(!pattern.matches(0xC015));
+        assert_eq!(pattern.to_string(), "D???");
+
+        let cls = OpcodePattern::parse("00e0").unwrap();
+        assert!(cls.matches(0x00E0) && !cls.matches(0x00EE));
+        assert_eq!(cls.to_string(), "00E0");
+
+        assert_eq!(OpcodePattern::parse("00E"), None);
+        assert_eq!(OpcodePattern::parse("00E00"), None);
+    }
+
+    #[test]
+    fn watchpoints_parse_registers_addresses_and_ranges() {
+        assert_eq!(
+            Watchpoint::parse("i"),
+            Some(Watchpoint::Register(Register::I))
+        );
+        assert_eq!(
+            Watchpoint::parse("vA"),
+            Some(Watchpoint::Register(Register::V(0xA)))
+        );
+        assert_eq!(
+            Watchpoint::parse("0x300"),
+            Some(Watchpoint::Memory {
+                range: 0x300..=0x300,
+                access: Access::Any
+            })
+        );
+        assert_eq!(
+            Watchpoint::parse("0x300-0x30F:w"),
+            Some(Watchpoint::Memory {
+                range: 0x300..=0x30F,
+                access: Access::Write
+            })
+        );
+        assert_eq!(Watchpoint::parse("768:r").unwrap().to_string(), "0x300:r");
+        for bad in ["VG", "V10", "0x300:x", "0x300-", "J"] {
+            assert_eq!(Watchpoint::parse(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn memory_watchpoints_only_fire_on_their_access() {
+        let breakpoints = Breakpoints {
+            watchpoints: vec![Watchpoint::parse("0x300-0x30F:w").unwrap()],
+            ..Breakpoints::default()
+        };
+        assert_eq!(breakpoints.check_memory(0x200, 0x305, false), None);
+        assert_eq!(breakpoints.check_memory(0x200, 0x310, true), None);
+        assert_eq!(
+            breakpoints.check_memory(0x200, 0x305, true),
+            Some(StopReason::MemoryWrite {
+                pc: 0x200,
+                index: 0x305
+            })
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::instruction::{decode_at, Instruction};
+
+const ORIGIN: usize = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataStyle {
+    Hex,
+    Sprites,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Subroutine,
+}
+
+/// `asm` starts programs at `main`, so that is what the entry point is
+/// called.
+fn label_name(kind: LabelKind, address: usize) -> String {
+    if address == ORIGIN {
+        return "main".to_owned();
+    }
+    match kind {
+        LabelKind::Subroutine => format!("sub_{address:03X}"),
+        LabelKind::Jump => format!("loc_{address:03X}"),
+        LabelKind::Data => format!("dat_{address:03X}"),
+    }
+}
+
+/// Addresses reachable from the entry point by following execution flow.
+/// Computed jumps (`jump0`) cannot be followed and end their path.
+fn trace_code(program: &[u8]) -> (BTreeMap<usize, Instruction>, BTreeMap<usize, LabelKind>) {
+    let end = ORIGIN + program.len();
+    let mut code = BTreeMap::new();
+    let mut labels = BTreeMap::new();
+    let mut pending = vec![ORIGIN];
+
+    while let Some(address) = pending.pop() {
+        if address < ORIGIN || address + 1 >= end || code.contains_key(&address) {
+            continue;
+        }
+        let Ok(instruction) = decode_at(program, address - ORIGIN) else {
+            continue;
+        };
+        code.insert(address, instruction);
+
+        let next = address + instruction.len();
+        match instruction {
+            Instruction::Ret | Instruction::Exit => {}
+            Instruction::Jp(nnn) => {
+                labels.entry(nnn as usize).or_insert(LabelKind::Jump);
+                pending.push(nnn as usize);
+            }
+            Instruction::JpV0(nnn) => {
+                labels.entry(nnn as usize).or_insert(LabelKind::Jump);
+            }
+            Instruction::Call(nnn) => {
+                labels.insert(nnn as usize, LabelKind::Subroutine);
+                pending.push(nnn as usize);
+                pending.push(next);
+            }
+            Instruction::SeImm(..)
+            | Instruction::SneImm(..)
+            | Instruction::SeReg(..)
+            | Instruction::SneReg(..)
+            | Instruction::Skp(_)
+            | Instruction::Sknp(_) => {
+                // XO-CHIP skips over the whole of a following F000 NNNN.
+                let long = program.get(next - ORIGIN..next - ORIGIN + 2) == Some(&[0xF0, 0x00]);
+                pending.push(next);
+                pending.push(next + if long { 4 } else { 2 });
+            }
+            Instruction::LdI(nnn) => {
+                labels.entry(nnn as usize).or_insert(LabelKind::Data);
+                pending.push(next);
+            }
+            Instruction::LdILong(nnnn) => {
+                labels.entry(nnnn as usize).or_insert(LabelKind::Data);
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+    (code, labels)
+}
+
+/// The instruction in the Octo syntax `asm` reads. `target` and
+/// `long_target` name the address operands of jumps, calls and I loads.
+fn octo(
+    instruction: Instruction,
+    target: impl Fn(u16) -> String,
+    long_target: impl Fn(u16) -> String,
+) -> String {
+    use Instruction::*;
+
+    match instruction {
+        ScrollDown(n) => format!("scroll-down {n}"),
+        ScrollUp(n) => format!("scroll-up {n}"),
+        Cls => "clear".to_owned(),
+        Ret => "return".to_owned(),
+        ScrollRight => "scroll-right".to_owned(),
+        ScrollLeft => "scroll-left".to_owned(),
+        Exit => "exit".to_owned(),
+        Low => "lores".to_owned(),
+        High => "hires".to_owned(),
+        Jp(nnn) => format!("jump {}", target(nnn)),
+        Call(nnn) => format!(":call {}", target(nnn)),
+        // Octo writes skips as the condition under which the next
+        // instruction does run.
+        SeImm(x, nn) => format!("if v{x:x} != {nn:#04X} then"),
+        SneImm(x, nn) => format!("if v{x:x} == {nn:#04X} then"),
+        SeReg(x, y) => format!("if v{x:x} != v{y:x} then"),
+        SneReg(x, y) => format!("if v{x:x} == v{y:x} then"),
+        Skp(x) => format!("if v{x:x} -key then"),
+        Sknp(x) => format!("if v{x:x} key then"),
+        SaveRange(x, y) => format!("save v{x:x} - v{y:x}"),
+        LoadRange(x, y) => format!("load v{x:x} - v{y:x}"),
+        LdImm(x, nn) => format!("v{x:x} := {nn:#04X}"),
+        AddImm(x, nn) => format!("v{x:x} += {nn:#04X}"),
+        LdReg(x, y) => format!("v{x:x} := v{y:x}"),
+        Or(x, y) => format!("v{x:x} |= v{y:x}"),
+        And(x, y) => format!("v{x:x} &= v{y:x}"),
+        Xor(x, y) => format!("v{x:x} ^= v{y:x}"),
+        AddReg(x, y) => format!("v{x:x} += v{y:x}"),
+        Sub(x, y) => format!("v{x:x} -= v{y:x}"),
+        Shr(x, y) => format!("v{x:x} >>= v{y:x}"),
+        Subn(x, y) => format!("v{x:x} =- v{y:x}"),
+        Shl(x, y) => format!("v{x:x} <<= v{y:x}"),
+        LdI(nnn) => format!("i := {}", target(nnn)),
+        JpV0(nnn) => format!("jump0 {}", target(nnn)),
+        Rnd(x, nn) => format!("v{x:x} := random {nn:#04X}"),
+        Drw(x, y, n) => format!("sprite v{x:x} v{y:x} {n}"),
+        LdVxDt(x) => format!("v{x:x} := delay"),
+        LdVxK(x) => format!("v{x:x} := key"),
+        LdDtVx(x) => format!("delay := v{x:x}"),
+        LdStVx(x) => format!("buzzer := v{x:x}"),
+        AddI(x) => format!("i += v{x:x}"),
+        LdF(x) => format!("i := hex v{x:x}"),
+        LdHf(x) => format!("i := bighex v{x:x}"),
+        LdB(x) => format!("bcd v{x:x}"),
+        LdIVx(x) => format!("save v{x:x}"),
+        LdVxI(x) => format!("load v{x:x}"),
+        LdRVx(x) => format!("saveflags v{x:x}"),
+        LdVxR(x) => format!("loadflags v{x:x}"),
+        LdILong(nnnn) => format!("i := long {}", long_target(nnnn)),
+        Plane(n) => format!("plane {n}"),
+        Audio => "audio".to_owned(),
+        Pitch(x) => format!("pitch := v{x:x}"),
+    }
+}
+
+fn sprite_row(byte: u8) -> String {
+    (0..8)
+        .rev()
+        .map(|bit| if byte >> bit & 1 != 0 { '#' } else { '.' })
+        .collect()
+}
+
+/// Disassembles a ROM loaded at 0x200 into Octo. Reachable code is printed
+/// as statements with branch targets replaced by labels, everything else as
+/// byte literals, so `asm::assemble` turns the output back into the same
+/// ROM.
+pub fn disassemble(program: &[u8], data_style: DataStyle) -> String {
+    let end = ORIGIN + program.len();
+    let (code, mut labels) = trace_code(program);
+
+    // Only keep labels that land on an instruction or data boundary, as
+    // those are the only ones that can be emitted.
+    let mut boundaries = BTreeSet::new();
+    let mut address = ORIGIN;
+    while address < end {
+        boundaries.insert(address);
+        address += code
+            .get(&address)
+            .map_or(1, |instruction| instruction.len());
+    }
+    labels.retain(|address, _| boundaries.contains(address));
+
+    let operand = |nnn: u16| match labels.get(&(nnn as usize)) {
+        Some(&kind) => label_name(kind, nnn as usize),
+        None => format!("{nnn:#05X}"),
+    };
+    let long_operand = |nnnn: u16| match labels.get(&(nnnn as usize)) {
+        Some(&kind) => label_name(kind, nnnn as usize),
+        None => format!("{nnnn:#06X}"),
+    };
+
+    let mut out = String::new();
+    let mut address = ORIGIN;
+    while address < end {
+        if let Some(&kind) = labels.get(&address) {
+            writeln!(out, ": {}", label_name(kind, address)).unwrap();
+        } else if address == ORIGIN {
+            writeln!(out, ": main").unwrap();
+        }
+        let offset = address - ORIGIN;
+
+        if let Some(&instruction) = code.get(&address) {
+            let bytes = &program[offset..offset + instruction.len()];
+            let text = octo(instruction, operand, long_operand);
+            let hex: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+            writeln!(out, "    {text:<24} # {address:#05X}  {hex}").unwrap();
+            address += instruction.len();
+            continue;
+        }
+
+        let run_end = (address + 1..end)
+            .find(|a| code.contains_key(a) || labels.contains_key(a))
+            .unwrap_or(end);
+        match data_style {
+            DataStyle::Hex => {
+                for (i, chunk) in program[offset..run_end - ORIGIN].chunks(8).enumerate() {
+                    let bytes: Vec<String> = chunk.iter().map(|b| format!("{b:#04X}")).collect();
+                    writeln!(
+                        out,
+                        "    {:<24} # {:#05X}",
+                        bytes.join(" "),
+                        address + i * 8
+                    )
+                    .unwrap();
+                }
+            }
+            DataStyle::Sprites => {
+                for (i, &byte) in program[offset..run_end - ORIGIN].iter().enumerate() {
+                    writeln!(
+                        out,
+                        "    {:<24} # {:#05X}  {}",
+                        format!("{byte:#04X}"),
+                        address + i,
+                        sprite_row(byte)
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        address = run_end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn assert_round_trip(rom: &[u8]) {
+        // The assembler pads odd output to whole instructions.
+        let mut expected = rom.to_vec();
+        if expected.len() % 2 == 1 {
+            expected.push(0);
+        }
+        for style in [DataStyle::Hex, DataStyle::Sprites] {
+            let source = disassemble(rom, style);
+            assert_eq!(assemble(&source).unwrap(), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn sample_roms_reassemble_to_the_same_bytes() {
+        assert_round_trip(include_bytes!("../sample/ibmlogo.ch8"));
+        assert_round_trip(include_bytes!("../sample/br8kout.ch8"));
+        assert_round_trip(include_bytes!("../sample/pumpkindressup.ch8"));
+    }
+
+    #[test]
+    fn xo_chip_long_loads_reassemble() {
+        assert_round_trip(&[
+            0x30, 0x00, // if v0 != 0x00 then
+            0xF0, 0x00, 0x02, 0x0A, // i := long dat_20A
+            0x53, 0x12, // save v3 - v1
+            0x00, 0xFD, // exit
+            0x55, 0xAA,
+        ]);
+    }
+
+    #[test]
+    fn skips_become_the_opposite_condition() {
+        let source = disassemble(&[0x3A, 0x05, 0xE1, 0x9E, 0x00, 0xEE], DataStyle::Hex);
+        assert!(source.contains("if va != 0x05 then"), "{source}");
+        assert!(source.contains("if v1 -key then"), "{source}");
+    }
+}
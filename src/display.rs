@@ -1,49 +1,31 @@
 use crate::app::ColorConfig;
+use crate::CHIP8;
 use pixels::Pixels;
 
 pub struct Display(pub Pixels, pub ColorConfig);
 
 impl Display {
-    pub fn set_pixel(&mut self, x: u8, y: u8) {
-        let fb = self.0.frame_mut();
-        let x = x as usize;
-        let y = y as usize;
-        fb[(y * 64 + x) * 4] = self.1.fg_on_color.0;
-        fb[(y * 64 + x) * 4 + 1] = self.1.fg_on_color.1;
-        fb[(y * 64 + x) * 4 + 2] = self.1.fg_on_color.2;
-        fb[(y * 64 + x) * 4 + 3] = 255;
-    }
-
-    pub fn unset_pixel(&mut self, x: u8, y: u8) {
-        let fb = self.0.frame_mut();
-        let x = x as usize;
-        let y = y as usize;
-        fb[(y * 64 + x) * 4] = self.1.fg_off_color.0;
-        fb[(y * 64 + x) * 4 + 1] = self.1.fg_off_color.1;
-        fb[(y * 64 + x) * 4 + 2] = self.1.fg_off_color.2;
-        fb[(y * 64 + x) * 4 + 3] = 255;
-    }
-
-    pub fn get_pixel(&self, x: u8, y: u8) -> bool {
-        let fb = self.0.frame();
-        let (x, y) = (x as usize, y as usize);
-        !(fb[(y * 64 + x) * 4] == self.1.fg_off_color.0
-            && fb[(y * 64 + x) * 4 + 1] == self.1.fg_off_color.1
-            && fb[(y * 64 + x) * 4 + 2] == self.1.fg_off_color.2)
-    }
+    /// Converts the machine's bitplanes to RGBA through the palette, first
+    /// resizing the pixel buffer if the machine switched between lo-res and
+    /// hi-res. Returns whether it did, so the window can follow.
+    pub fn draw(&mut self, chip8: &CHIP8) -> bool {
+        let (width, height) = chip8.display_size();
+        let resized = self.0.frame().len() != width * height * 4;
+        if resized {
+            self.0.resize_buffer(width as u32, height as u32).unwrap();
+        }
 
-    pub fn clear_screen(&mut self) {
-        let fb = self.0.frame_mut();
-        for i in 0..fb.len() {
-            if (i + 1) % 4 == 0 {
-                fb[i] = 255;
-            } else if i % 4 == 0 {
-                fb[i] = self.1.fg_off_color.0;
-            } else if (i + 3) % 4 == 0 {
-                fb[i] = self.1.fg_off_color.1;
-            } else {
-                fb[i] = self.1.fg_off_color.2;
-            }
+        let palette = [
+            self.1.fg_off_color,
+            self.1.fg_on_color,
+            self.1.fg_plane2_color,
+            self.1.fg_both_color,
+        ];
+        let indices = (0..height).flat_map(|y| (0..width).map(move |x| chip8.pixel(x, y)));
+        for (pixel, index) in self.0.frame_mut().chunks_exact_mut(4).zip(indices) {
+            let (r, g, b) = palette[index as usize];
+            pixel.copy_from_slice(&[r, g, b, 255]);
         }
+        resized
     }
 }
@@ -0,0 +1,408 @@
+use std::fmt;
+
+use crate::CHIP8;
+
+/// An expression over the machine state, such as `V3 == 5 && I > 0x300` or
+/// `[I+2] != 0`. Values are integers, comparisons and `&&`/`||` give 1 or
+/// 0, and anything non-zero is true.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Variable(Variable),
+    /// The byte at an address, written `[address]`.
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    V(u8),
+    I,
+    Pc,
+    /// Delay timer.
+    Dt,
+    /// Sound timer.
+    St,
+    /// Stack depth.
+    Sp,
+    /// How many times execution has reached the current `pc`.
+    Hits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Binary operators from the loosest binding to the tightest, in the order
+/// C uses.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 10] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+    ],
+];
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let expr = parser.binary(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected \"{token}\"")),
+        }
+    }
+
+    /// `hits` is the value of the `hits` variable. Reading outside of
+    /// memory or dividing by zero gives `None`.
+    pub fn eval(&self, chip8: &CHIP8, hits: u32) -> Option<i64> {
+        Some(match self {
+            Expr::Number(n) => *n,
+            Expr::Variable(variable) => match variable {
+                Variable::V(x) => chip8.vx_reg[*x as usize] as i64,
+                Variable::I => chip8.i_reg as i64,
+                Variable::Pc => chip8.pc as i64,
+                Variable::Dt => chip8.delay_timer as i64,
+                Variable::St => chip8.sound_timer as i64,
+                Variable::Sp => chip8.stack.len() as i64,
+                Variable::Hits => hits as i64,
+            },
+            Expr::Memory(address) => {
+                let address = usize::try_from(address.eval(chip8, hits)?).ok()?;
+                *chip8.ram.get(address)? as i64
+            }
+            Expr::Unary(op, operand) => {
+                let value = operand.eval(chip8, hits)?;
+                match op {
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Complement => !value,
+                }
+            }
+            Expr::Binary(BinaryOp::Or, left, right) => {
+                (left.eval(chip8, hits)? != 0 || right.eval(chip8, hits)? != 0) as i64
+            }
+            Expr::Binary(BinaryOp::And, left, right) => {
+                (left.eval(chip8, hits)? != 0 && right.eval(chip8, hits)? != 0) as i64
+            }
+            Expr::Binary(op, left, right) => {
+                let (a, b) = (left.eval(chip8, hits)?, right.eval(chip8, hits)?);
+                match op {
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Shl => a.wrapping_shl(b as u32),
+                    BinaryOp::Shr => a.wrapping_shr(b as u32),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div => a.checked_div(b)?,
+                    BinaryOp::Rem => a.checked_rem(b)?,
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Name(name) => write!(f, "{name}"),
+            Token::Symbol(symbol) => write!(f, "{symbol}"),
+        }
+    }
+}
+
+/// Every symbol, longer ones first so that `<=` is not read as `<`.
+const SYMBOLS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "|", "^", "&", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")", "[", "]",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            tokens.push(if c.is_ascii_digit() {
+                Token::Number(parse_number(word).ok_or(format!("bad number \"{word}\""))?)
+            } else {
+                Token::Name(word.to_uppercase())
+            });
+            len
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(**symbol))
+                .ok_or(format!("unexpected \"{c}\""))?;
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    let word = word.to_lowercase();
+    match (word.strip_prefix("0x"), word.strip_prefix("0b")) {
+        (Some(hex), _) => i64::from_str_radix(hex, 16).ok(),
+        (_, Some(binary)) => i64::from_str_radix(binary, 2).ok(),
+        _ => word.parse().ok(),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.peek().cloned().ok_or("unexpected end")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        match self.next()? {
+            Token::Symbol(s) if s == symbol => Ok(()),
+            token => Err(format!("expected \"{symbol}\" but found \"{token}\"")),
+        }
+    }
+
+    /// Parses operators of `level` in `PRECEDENCE` and tighter, left to
+    /// right.
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(operators) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some(&(_, op)) = operators
+            .iter()
+            .find(|(symbol, _)| self.peek() == Some(&Token::Symbol(symbol)))
+        {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek() {
+            Some(Token::Symbol("!")) => UnaryOp::Not,
+            Some(Token::Symbol("-")) => UnaryOp::Negate,
+            Some(Token::Symbol("~")) => UnaryOp::Complement,
+            _ => return self.primary(),
+        };
+        self.position += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next()? {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Name(name) => Ok(Expr::Variable(variable(&name)?)),
+            Token::Symbol("(") => {
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Symbol("[") => {
+                let expr = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(expr)))
+            }
+            token => Err(format!("unexpected \"{token}\"")),
+        }
+    }
+}
+
+fn variable(name: &str) -> Result<Variable, String> {
+    Ok(match name {
+        "I" => Variable::I,
+        "PC" => Variable::Pc,
+        "DT" => Variable::Dt,
+        "ST" => Variable::St,
+        "SP" => Variable::Sp,
+        "HITS" => Variable::Hits,
+        _ => match name.strip_prefix('V').map(|x| u8::from_str_radix(x, 16)) {
+            Some(Ok(x)) if name.len() == 2 => Variable::V(x),
+            _ => return Err(format!("unknown variable \"{name}\"")),
+        },
+    })
+}
+
+/// A log message with `{expression}` placeholders, printed in decimal or
+/// with `{expression:x}` in hexadecimal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<Piece>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Value { expr: Expr, hex: bool },
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut pieces = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find('{') {
+            pieces.push(Piece::Text(rest[..start].to_owned()));
+            let end = rest[start..]
+                .find('}')
+                .ok_or("unclosed \"{\" in the message")?
+                + start;
+            let inner = &rest[start + 1..end];
+            let (inner, hex) = match inner.strip_suffix(":x") {
+                Some(inner) => (inner, true),
+                None => (inner, false),
+            };
+            pieces.push(Piece::Value {
+                expr: Expr::parse(inner)?,
+                hex,
+            });
+            rest = &rest[end + 1..];
+        }
+        pieces.push(Piece::Text(rest.to_owned()));
+        Ok(Template(pieces))
+    }
+
+    /// Values that cannot be evaluated are shown as `?`.
+    pub fn format(&self, chip8: &CHIP8, hits: u32) -> String {
+        let mut out = String::new();
+        for piece in &self.0 {
+            match piece {
+                Piece::Text(text) => out.push_str(text),
+                Piece::Value { expr, hex } => match expr.eval(chip8, hits) {
+                    Some(value) if *hex => out.push_str(&format!("{value:#X}")),
+                    Some(value) => out.push_str(&value.to_string()),
+                    None => out.push('?'),
+                },
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Platform, Quirks};
+
+    fn eval(source: &str) -> Option<i64> {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8.vx_reg[3] = 5;
+        chip8.i_reg = 0x301;
+        chip8.ram[0x303] = 0xAB;
+        Expr::parse(source).unwrap().eval(&chip8, 11)
+    }
+
+    #[test]
+    fn operators_bind_by_precedence_and_left_to_right() {
+        assert_eq!(eval("1 + 2 * 3"), Some(7));
+        assert_eq!(eval("(1 + 2) * 3"), Some(9));
+        assert_eq!(eval("10 - 4 - 3"), Some(3));
+        assert_eq!(eval("1 << 2 + 1"), Some(8));
+        assert_eq!(eval("1 || 0 && 0"), Some(1));
+        assert_eq!(eval("6 & 3 == 2"), Some(0));
+        assert_eq!(eval("(6 & 3) == 2"), Some(1));
+        assert_eq!(eval("1 | 2 == 2"), Some(1));
+        assert_eq!(eval("1 == 1 < 2"), Some(1));
+        assert_eq!(eval("3 ^ 1 & 2"), Some(3));
+        assert_eq!(eval("0b101 ^ 1 | 0x10"), Some(0x14));
+        assert_eq!(eval("-2 * -3 + ~0 + !7"), Some(5));
+        assert_eq!(eval("7 % 4 / 0"), None);
+    }
+
+    #[test]
+    fn variables_and_memory_read_the_machine() {
+        assert_eq!(eval("V3 == 5 && I > 0x300"), Some(1));
+        assert_eq!(eval("[I+2]"), Some(0xAB));
+        assert_eq!(eval("pc == 0x200 && hits > 10"), Some(1));
+        assert_eq!(eval("[0x10000]"), None);
+    }
+
+    #[test]
+    fn malformed_expressions_are_errors() {
+        for source in ["", "1 +", "(1", "[I", "V3 5", "VG", "foo", "1 ) 2"] {
+            assert!(Expr::parse(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn templates_format_values_in_decimal_and_hex() {
+        let chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        let template = Template::parse("at {PC:x}, hit {hits} times, {1/0}").unwrap();
+        assert_eq!(template.format(&chip8, 3), "at 0x200, hit 3 times, ?");
+        assert!(Template::parse("score {V3").is_err());
+    }
+}
@@ -0,0 +1,144 @@
+/// Size in bytes of a 4x5 hex font: sixteen glyphs of five rows each.
+pub const FONT_SIZE: usize = 80;
+
+/// The 4x5 hex fonts different interpreters shipped. ROMs that draw digits
+/// with FX29 look the way their authors saw them only with the matching set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    /// Also the font most modern interpreters use.
+    Octo,
+    Vip,
+    Dream6800,
+    Eti660,
+    FishNChips,
+}
+
+impl Font {
+    pub fn from_name(name: &str) -> Option<Font> {
+        Some(match name.to_lowercase().as_str() {
+            "octo" => Font::Octo,
+            "vip" | "cosmac-vip" => Font::Vip,
+            "dream6800" | "dream-6800" => Font::Dream6800,
+            "eti660" | "eti-660" => Font::Eti660,
+            "fishnchips" | "fish-n-chips" => Font::FishNChips,
+            _ => return None,
+        })
+    }
+
+    #[rustfmt::skip]
+    pub fn bytes(self) -> &'static [u8; FONT_SIZE] {
+        match self {
+            Font::Octo => &[
+                0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+                0x20, 0x60, 0x20, 0x20, 0x70, // 1
+                0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+                0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+                0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+                0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+                0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+                0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+                0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+                0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+                0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+                0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+                0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+                0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+                0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+                0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+            ],
+            Font::Vip => &[
+                0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+                0x60, 0x20, 0x20, 0x20, 0x70, // 1
+                0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+                0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+                0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+                0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+                0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+                0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+                0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+                0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+                0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+                0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+                0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+                0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+                0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+                0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+            ],
+            Font::Dream6800 => &[
+                0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+                0x40, 0x40, 0x40, 0x40, 0x40, // 1
+                0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+                0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+                0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+                0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+                0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+                0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+                0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+                0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+                0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+                0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+                0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+                0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+                0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+                0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+            ],
+            Font::Eti660 => &[
+                0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+                0x20, 0x20, 0x20, 0x20, 0x20, // 1
+                0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+                0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+                0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+                0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+                0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+                0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+                0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+                0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+                0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+                0x80, 0x80, 0xE0, 0xA0, 0xE0, // B
+                0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+                0x20, 0x20, 0xE0, 0xA0, 0xE0, // D
+                0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+                0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+            ],
+            Font::FishNChips => &[
+                0x60, 0xA0, 0xA0, 0xA0, 0xC0, // 0
+                0x40, 0xC0, 0x40, 0x40, 0xE0, // 1
+                0xC0, 0x20, 0x40, 0x80, 0xE0, // 2
+                0xC0, 0x20, 0x40, 0x20, 0xC0, // 3
+                0x20, 0xA0, 0xE0, 0x20, 0x20, // 4
+                0xE0, 0x80, 0xC0, 0x20, 0xC0, // 5
+                0x40, 0x80, 0xC0, 0xA0, 0x40, // 6
+                0xE0, 0x20, 0x60, 0x40, 0x40, // 7
+                0x40, 0xA0, 0x40, 0xA0, 0x40, // 8
+                0x40, 0xA0, 0x60, 0x20, 0x40, // 9
+                0x40, 0xA0, 0xE0, 0xA0, 0xA0, // A
+                0xC0, 0xA0, 0xC0, 0xA0, 0xC0, // B
+                0x60, 0x80, 0x80, 0x80, 0x60, // C
+                0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+                0xE0, 0x80, 0xC0, 0x80, 0xE0, // E
+                0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+            ],
+        }
+    }
+}
+
+/// The SUPER-CHIP 8x10 digits used by FX30.
+#[rustfmt::skip]
+pub const BIG_FONT: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
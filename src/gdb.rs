@@ -0,0 +1,516 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Range;
+
+use crate::app::Debugger;
+use crate::{CHIP8Input, CHIP8};
+
+/// Register numbers as gdb sees them: V0-VF, then I, PC, DT, ST and SP
+/// last.
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_DT: usize = 18;
+const REG_ST: usize = 19;
+const REG_SP: usize = 20;
+const REGISTERS: usize = 21;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="16" type="uint16"/>
+  </feature>
+</target>
+"#;
+
+/// Stop replies: trapped at a breakpoint or step, interrupted, or halted by
+/// an emulation error.
+const SIGTRAP: &str = "S05";
+const SIGINT: &str = "S02";
+const SIGILL: &str = "S04";
+
+/// Speaks the GDB remote serial protocol over TCP on localhost. Packets are
+/// handled once per frame, and the machine stays paused until gdb
+/// connects and continues it.
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    buffer: Vec<u8>,
+    no_ack: bool,
+    /// Set while gdb waits for the machine to stop after `c`.
+    running: bool,
+    /// Set once the machine halted for good, to refuse further execution.
+    halted: bool,
+    /// Set until the first client connects.
+    waiting: bool,
+}
+
+impl GdbStub {
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            listener,
+            client: None,
+            buffer: Vec::new(),
+            no_ack: false,
+            running: false,
+            halted: false,
+            waiting: true,
+        })
+    }
+
+    /// Reads whatever the client sent without waiting. Returns false once
+    /// it has disconnected.
+    fn receive(&mut self) -> bool {
+        let Some(client) = &mut self.client else {
+            return false;
+        };
+        let mut chunk = [0; 4096];
+        loop {
+            match client.read(&mut chunk) {
+                Ok(0) => return false,
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return true,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+    }
+
+    fn send_raw(&mut self, bytes: &[u8]) {
+        let Some(client) = &mut self.client else {
+            return;
+        };
+        let sent = client
+            .set_nonblocking(false)
+            .and_then(|()| client.write_all(bytes))
+            .and_then(|()| client.set_nonblocking(true));
+        if sent.is_err() {
+            self.client = None;
+        }
+    }
+
+    fn send(&mut self, payload: &str) {
+        self.send_raw(frame(payload).as_bytes());
+    }
+
+    /// Takes the next complete packet, or `\x03` for an interrupt, off the
+    /// buffer.
+    fn next_packet(&mut self) -> Option<Vec<u8>> {
+        loop {
+            match self.buffer.first()? {
+                b'+' | b'-' => {
+                    self.buffer.remove(0);
+                }
+                0x03 => {
+                    self.buffer.remove(0);
+                    return Some(vec![0x03]);
+                }
+                b'$' => {
+                    let end = self.buffer.iter().position(|&b| b == b'#')?;
+                    if self.buffer.len() < end + 3 {
+                        return None;
+                    }
+                    let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+                    let payload = packet[1..end].to_vec();
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                    let sum = checksum_of(&payload);
+                    if !self.no_ack {
+                        let ack: &[u8] = if checksum == Some(sum) { b"+" } else { b"-" };
+                        self.send_raw(ack);
+                    }
+                    if checksum == Some(sum) {
+                        return Some(payload);
+                    }
+                }
+                _ => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+    }
+
+    /// Answers a packet. Returns false when gdb asked to kill the target.
+    fn handle(
+        &mut self,
+        packet: &str,
+        chip8: &mut CHIP8,
+        input: &CHIP8Input,
+        paused: &mut bool,
+    ) -> bool {
+        if packet == "QStartNoAckMode" {
+            // Acknowledged with the old rules, before acks stop.
+            self.send("OK");
+            self.no_ack = true;
+            return true;
+        }
+        let (command, rest) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => SIGTRAP.to_owned(),
+            "g" => (0..REGISTERS)
+                .map(|n| read_register(chip8, n))
+                .collect::<String>(),
+            "G" => {
+                let mut rest = rest;
+                for n in 0..REGISTERS {
+                    let width = register_width(n) * 2;
+                    let Some(value) = rest.get(..width) else {
+                        break;
+                    };
+                    write_register(chip8, n, value);
+                    rest = &rest[width..];
+                }
+                "OK".to_owned()
+            }
+            "p" => match usize::from_str_radix(rest, 16) {
+                Ok(n) if n < REGISTERS => read_register(chip8, n),
+                _ => "E01".to_owned(),
+            },
+            "P" => match rest.split_once('=') {
+                Some((n, value)) => match usize::from_str_radix(n, 16) {
+                    Ok(n) if n < REGISTERS && write_register(chip8, n, value) => "OK".to_owned(),
+                    _ => "E01".to_owned(),
+                },
+                None => "E01".to_owned(),
+            },
+            "m" => match parse_range(rest) {
+                Some(range) => match chip8.ram.get(range) {
+                    Some(bytes) => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+                    None => "E01".to_owned(),
+                },
+                None => "E01".to_owned(),
+            },
+            "M" => {
+                let written = rest.split_once(':').and_then(|(range, data)| {
+                    let range = parse_range(range)?;
+                    let bytes = decode_hex(data).filter(|bytes| bytes.len() == range.len())?;
+                    chip8.ram.get_mut(range)?.copy_from_slice(&bytes);
+                    Some(())
+                });
+                match written {
+                    Some(()) => "OK".to_owned(),
+                    None => "E01".to_owned(),
+                }
+            }
+            "Z" | "z" => {
+                let mut fields = rest.split(',');
+                let kind = fields.next();
+                let address = fields.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+                match (kind, address) {
+                    (Some("0" | "1"), Some(address)) => {
+                        let addresses = &mut chip8.breakpoints_mut().addresses;
+                        match command {
+                            "Z" => addresses.insert(address),
+                            _ => addresses.remove(&address),
+                        };
+                        "OK".to_owned()
+                    }
+                    // Watchpoints are not supported over gdb.
+                    _ => String::new(),
+                }
+            }
+            "s" | "c" if self.halted => SIGILL.to_owned(),
+            "s" => {
+                chip8.resume();
+                match chip8.update(input.clone()) {
+                    Ok(out) if out.exit_requested => {
+                        self.halted = true;
+                        "W00".to_owned()
+                    }
+                    Ok(_) => SIGTRAP.to_owned(),
+                    Err(_) => {
+                        self.halted = true;
+                        SIGILL.to_owned()
+                    }
+                }
+            }
+            "c" => {
+                chip8.resume();
+                *paused = false;
+                self.running = true;
+                return true;
+            }
+            "k" => return false,
+            "D" => {
+                self.send("OK");
+                self.client = None;
+                *paused = false;
+                return true;
+            }
+            "H" => "OK".to_owned(),
+            "q" | "Q" | "v" => self.query(packet),
+            _ => String::new(),
+        };
+        self.send(&reply);
+        true
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_owned();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some(range) = parse_range(range) else {
+                return "E01".to_owned();
+            };
+            let xml = TARGET_XML.as_bytes();
+            let start = range.start.min(xml.len());
+            let end = range.end.min(xml.len());
+            let prefix = if end == xml.len() { "l" } else { "m" };
+            return format!("{prefix}{}", String::from_utf8_lossy(&xml[start..end]));
+        }
+        match packet {
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        }
+        .to_owned()
+    }
+}
+
+impl Debugger for GdbStub {
+    fn frame(&mut self, chip8: &mut CHIP8, input: &CHIP8Input, paused: &mut bool) -> bool {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((client, _)) if client.set_nonblocking(true).is_ok() => {
+                    self.client = Some(client);
+                    self.buffer.clear();
+                    self.no_ack = false;
+                    self.running = false;
+                    self.waiting = false;
+                    *paused = true;
+                }
+                _ => {
+                    *paused |= self.waiting;
+                    return true;
+                }
+            }
+        }
+        if !self.receive() {
+            self.client = None;
+            return true;
+        }
+        if self.running && *paused {
+            self.running = false;
+            self.send(SIGTRAP);
+        }
+        while let Some(packet) = self.next_packet() {
+            if packet == [0x03] {
+                if self.running {
+                    self.running = false;
+                    *paused = true;
+                    self.send(SIGINT);
+                }
+                continue;
+            }
+            let packet = String::from_utf8_lossy(&packet).into_owned();
+            if !self.handle(&packet, chip8, input, paused) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn message(&mut self, text: String) {
+        println!("{text}");
+    }
+
+    fn halted(&mut self, exited: bool) {
+        self.halted = true;
+        if self.running {
+            self.running = false;
+            self.send(if exited { "W00" } else { SIGILL });
+        }
+    }
+}
+
+fn register_width(n: usize) -> usize {
+    match n {
+        REG_I | REG_PC | REG_SP => 2,
+        _ => 1,
+    }
+}
+
+/// Registers are sent as little-endian hex.
+fn read_register(chip8: &CHIP8, n: usize) -> String {
+    match n {
+        0..=15 => format!("{:02x}", chip8.vx_reg[n]),
+        REG_I => hex_le(chip8.i_reg),
+        REG_PC => hex_le(chip8.pc as u16),
+        REG_DT => format!("{:02x}", chip8.delay_timer),
+        REG_ST => format!("{:02x}", chip8.sound_timer),
+        // `StackConfig::validate` keeps the depth within 16 bits.
+        _ => hex_le(chip8.stack.len() as u16),
+    }
+}
+
+/// The stack depth cannot be written. Returns false for malformed values.
+fn write_register(chip8: &mut CHIP8, n: usize, value: &str) -> bool {
+    let Some(bytes) = decode_hex(value).filter(|bytes| bytes.len() == register_width(n)) else {
+        return false;
+    };
+    let word = || u16::from_le_bytes([bytes[0], bytes[1]]);
+    match n {
+        0..=15 => chip8.vx_reg[n] = bytes[0],
+        REG_I => chip8.i_reg = word(),
+        REG_PC => chip8.pc = word() as usize,
+        REG_DT => chip8.delay_timer = bytes[0],
+        REG_ST => chip8.sound_timer = bytes[0],
+        _ => {}
+    }
+    true
+}
+
+fn checksum_of(payload: &[u8]) -> u8 {
+    payload.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+fn frame(payload: &str) -> String {
+    format!("${payload}#{:02x}", checksum_of(payload.as_bytes()))
+}
+
+fn hex_le(value: u16) -> String {
+    let [low, high] = value.to_le_bytes();
+    format!("{low:02x}{high:02x}")
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Reads the `address,length` pair of `m`, `M` and `qXfer`. Ranges
+/// reaching past `usize::MAX` are rejected.
+fn parse_range(range: &str) -> Option<Range<usize>> {
+    let (address, len) = range.split_once(',')?;
+    let address = usize::from_str_radix(address, 16).ok()?;
+    let end = address.checked_add(usize::from_str_radix(len, 16).ok()?)?;
+    Some(address..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Platform, Quirks};
+
+    fn stub() -> GdbStub {
+        GdbStub::listen(0).unwrap()
+    }
+
+    /// Feeds `packet` to the stub as if gdb had sent it and returns the
+    /// reply gdb would read back.
+    fn exchange(chip8: &mut CHIP8, packet: &str) -> String {
+        let mut stub = stub();
+        let port = stub.listener.local_addr().unwrap().port();
+        let mut gdb = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        gdb.set_read_timeout(Some(std::time::Duration::from_millis(10)))
+            .unwrap();
+        gdb.write_all(frame(packet).as_bytes()).unwrap();
+        let mut reply = Vec::new();
+        let mut paused = true;
+        // A stub that never answers fails the test instead of hanging it.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "no reply to {packet:?}, got {:?}",
+                String::from_utf8_lossy(&reply)
+            );
+            stub.frame(chip8, &CHIP8Input::default(), &mut paused);
+            let mut chunk = [0; 256];
+            if let Ok(n) = gdb.read(&mut chunk) {
+                reply.extend_from_slice(&chunk[..n]);
+            }
+            let end = reply.iter().position(|&b| b == b'#');
+            if end.is_some_and(|end| reply.len() >= end + 3) {
+                break;
+            }
+        }
+        let reply = String::from_utf8(reply).unwrap();
+        let payload = reply.strip_prefix("+$").unwrap();
+        let (payload, checksum) = payload.split_once('#').unwrap();
+        assert_eq!(checksum, format!("{:02x}", checksum_of(payload.as_bytes())));
+        payload.to_owned()
+    }
+
+    #[test]
+    fn packets_are_framed_with_a_checksum() {
+        assert_eq!(frame("OK"), "$OK#9a");
+        assert_eq!(frame(""), "$#00");
+    }
+
+    #[test]
+    fn packets_are_split_off_the_buffer_and_checked() {
+        let mut stub = stub();
+        stub.buffer = b"+$g#67$m200,2#00\x03$?#3f$c".to_vec();
+        assert_eq!(stub.next_packet(), Some(b"g".to_vec()));
+        // The bad checksum is dropped and the interrupt comes through.
+        assert_eq!(stub.next_packet(), Some(vec![0x03]));
+        assert_eq!(stub.next_packet(), Some(b"?".to_vec()));
+        // An incomplete packet waits for the rest.
+        assert_eq!(stub.next_packet(), None);
+        assert_eq!(stub.buffer, b"$c");
+    }
+
+    #[test]
+    fn ranges_reaching_past_the_address_space_are_rejected() {
+        assert_eq!(parse_range("200,4"), Some(0x200..0x204));
+        assert_eq!(parse_range(&format!("{:x},2", usize::MAX)), None);
+        assert_eq!(parse_range("200"), None);
+    }
+
+    #[test]
+    fn memory_and_registers_are_served_over_tcp() {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8.load_program(&[0x60, 0x2A]).unwrap();
+        assert_eq!(exchange(&mut chip8, "m200,2"), "602a");
+        assert_eq!(exchange(&mut chip8, &format!("m{:x},2", usize::MAX)), "E01");
+        assert_eq!(
+            exchange(&mut chip8, &format!("M{:x},1:00", usize::MAX)),
+            "E01"
+        );
+        assert_eq!(
+            exchange(
+                &mut chip8,
+                &format!("qXfer:features:read:target.xml:{:x},10", usize::MAX)
+            ),
+            "E01"
+        );
+        // SP is 16 bits wide, so a deep stack does not spill into the next
+        // register.
+        chip8.stack = vec![0x200; 300];
+        assert_eq!(exchange(&mut chip8, "p14"), "2c01");
+        // Sixteen V registers and DT and ST of one byte, I, PC and SP of two.
+        assert_eq!(exchange(&mut chip8, "g").len(), (18 + 3 * 2) * 2);
+    }
+}
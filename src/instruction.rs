@@ -0,0 +1,922 @@
+use std::fmt;
+
+use crate::{
+    CHIP8Input, CHIP8Output, ExecError, Platform, CHIP8, FONT_SIZE, HIRES_HEIGHT, HIRES_WIDTH,
+    PLANES,
+};
+
+/// A decoded CHIP-8 instruction. `x`/`y` operands are register indices,
+/// `nn` byte immediates and `nnn` 12-bit addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[rustfmt::skip]
+pub enum Instruction {
+    ScrollDown(u8),     // 00CN (SCHIP)
+    ScrollUp(u8),       // 00DN (XO-CHIP)
+    Cls,                // 00E0
+    Ret,                // 00EE
+    ScrollRight,        // 00FB (SCHIP)
+    ScrollLeft,         // 00FC (SCHIP)
+    Exit,               // 00FD (SCHIP)
+    Low,                // 00FE (SCHIP)
+    High,               // 00FF (SCHIP)
+    Jp(u16),            // 1NNN
+    Call(u16),          // 2NNN
+    SeImm(u8, u8),      // 3XNN
+    SneImm(u8, u8),     // 4XNN
+    SeReg(u8, u8),      // 5XY0
+    SaveRange(u8, u8),  // 5XY2 (XO-CHIP)
+    LoadRange(u8, u8),  // 5XY3 (XO-CHIP)
+    LdImm(u8, u8),      // 6XNN
+    AddImm(u8, u8),     // 7XNN
+    LdReg(u8, u8),      // 8XY0
+    Or(u8, u8),         // 8XY1
+    And(u8, u8),        // 8XY2
+    Xor(u8, u8),        // 8XY3
+    AddReg(u8, u8),     // 8XY4
+    Sub(u8, u8),        // 8XY5
+    Shr(u8, u8),        // 8XY6
+    Subn(u8, u8),       // 8XY7
+    Shl(u8, u8),        // 8XYE
+    SneReg(u8, u8),     // 9XY0
+    LdI(u16),           // ANNN
+    JpV0(u16),          // BNNN
+    Rnd(u8, u8),        // CXNN
+    Drw(u8, u8, u8),    // DXYN
+    Skp(u8),            // EX9E
+    Sknp(u8),           // EXA1
+    LdVxDt(u8),         // FX07
+    LdVxK(u8),          // FX0A
+    LdDtVx(u8),         // FX15
+    LdStVx(u8),         // FX18
+    AddI(u8),           // FX1E
+    LdF(u8),            // FX29
+    LdHf(u8),           // FX30 (SCHIP)
+    LdB(u8),            // FX33
+    LdIVx(u8),          // FX55
+    LdVxI(u8),          // FX65
+    LdRVx(u8),          // FX75 (SCHIP)
+    LdVxR(u8),          // FX85 (SCHIP)
+    LdILong(u16),       // F000 NNNN (XO-CHIP)
+    Plane(u8),          // FN01 (XO-CHIP)
+    Audio,              // F002 (XO-CHIP)
+    Pitch(u8),          // FX3A (XO-CHIP)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown opcode {:#06X}", self.opcode)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes a single opcode. `F000`, whose operand is the following word,
+/// is rejected here; use `decode_at` to decode it.
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    use Instruction::*;
+
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let nn = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+
+    Ok(match opcode >> 12 {
+        0x0 => match nnn {
+            0x0C0..=0x0CF => ScrollDown(n),
+            0x0D0..=0x0DF => ScrollUp(n),
+            0x0E0 => Cls,
+            0x0EE => Ret,
+            0x0FB => ScrollRight,
+            0x0FC => ScrollLeft,
+            0x0FD => Exit,
+            0x0FE => Low,
+            0x0FF => High,
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x1 => Jp(nnn),
+        0x2 => Call(nnn),
+        0x3 => SeImm(x, nn),
+        0x4 => SneImm(x, nn),
+        0x5 => match n {
+            0x0 => SeReg(x, y),
+            0x2 => SaveRange(x, y),
+            0x3 => LoadRange(x, y),
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x6 => LdImm(x, nn),
+        0x7 => AddImm(x, nn),
+        0x8 => match n {
+            0x0 => LdReg(x, y),
+            0x1 => Or(x, y),
+            0x2 => And(x, y),
+            0x3 => Xor(x, y),
+            0x4 => AddReg(x, y),
+            0x5 => Sub(x, y),
+            0x6 => Shr(x, y),
+            0x7 => Subn(x, y),
+            0xE => Shl(x, y),
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x9 => match n {
+            0x0 => SneReg(x, y),
+            _ => return Err(DecodeError { opcode }),
+        },
+        0xA => LdI(nnn),
+        0xB => JpV0(nnn),
+        0xC => Rnd(x, nn),
+        0xD => Drw(x, y, n),
+        0xE => match nn {
+            0x9E => Skp(x),
+            0xA1 => Sknp(x),
+            _ => return Err(DecodeError { opcode }),
+        },
+        0xF => match nn {
+            0x01 => Plane(x),
+            0x02 if x == 0x0 => Audio,
+            0x07 => LdVxDt(x),
+            0x0A => LdVxK(x),
+            0x15 => LdDtVx(x),
+            0x18 => LdStVx(x),
+            0x1E => AddI(x),
+            0x29 => LdF(x),
+            0x30 => LdHf(x),
+            0x33 => LdB(x),
+            0x3A => Pitch(x),
+            0x55 => LdIVx(x),
+            0x65 => LdVxI(x),
+            0x75 => LdRVx(x),
+            0x85 => LdVxR(x),
+            _ => return Err(DecodeError { opcode }),
+        },
+        _ => return Err(DecodeError { opcode }),
+    })
+}
+
+/// Decodes the instruction starting at `address`, reading the operand word
+/// of `F000 NNNN` as well.
+pub fn decode_at(memory: &[u8], address: usize) -> Result<Instruction, DecodeError> {
+    let word = |a: usize| {
+        memory
+            .get(a..a + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    let opcode = word(address).ok_or(DecodeError { opcode: 0 })?;
+    if opcode == 0xF000 {
+        return word(address + 2)
+            .map(Instruction::LdILong)
+            .ok_or(DecodeError { opcode });
+    }
+    decode(opcode)
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            ScrollDown(n) => write!(f, "SCD {n}"),
+            ScrollUp(n) => write!(f, "SCU {n}"),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            Jp(nnn) => write!(f, "JP {nnn:#05X}"),
+            Call(nnn) => write!(f, "CALL {nnn:#05X}"),
+            SeImm(x, nn) => write!(f, "SE V{x:X}, {nn:#04X}"),
+            SneImm(x, nn) => write!(f, "SNE V{x:X}, {nn:#04X}"),
+            SeReg(x, y) => write!(f, "SE V{x:X}, V{y:X}"),
+            SaveRange(x, y) => write!(f, "LD [I], V{x:X}-V{y:X}"),
+            LoadRange(x, y) => write!(f, "LD V{x:X}-V{y:X}, [I]"),
+            LdImm(x, nn) => write!(f, "LD V{x:X}, {nn:#04X}"),
+            AddImm(x, nn) => write!(f, "ADD V{x:X}, {nn:#04X}"),
+            LdReg(x, y) => write!(f, "LD V{x:X}, V{y:X}"),
+            Or(x, y) => write!(f, "OR V{x:X}, V{y:X}"),
+            And(x, y) => write!(f, "AND V{x:X}, V{y:X}"),
+            Xor(x, y) => write!(f, "XOR V{x:X}, V{y:X}"),
+            AddReg(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Sub(x, y) => write!(f, "SUB V{x:X}, V{y:X}"),
+            Shr(x, y) => write!(f, "SHR V{x:X}, V{y:X}"),
+            Subn(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Shl(x, y) => write!(f, "SHL V{x:X}, V{y:X}"),
+            SneReg(x, y) => write!(f, "SNE V{x:X}, V{y:X}"),
+            LdI(nnn) => write!(f, "LD I, {nnn:#05X}"),
+            JpV0(nnn) => write!(f, "JP V0, {nnn:#05X}"),
+            Rnd(x, nn) => write!(f, "RND V{x:X}, {nn:#04X}"),
+            Drw(x, y, n) => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            Skp(x) => write!(f, "SKP V{x:X}"),
+            Sknp(x) => write!(f, "SKNP V{x:X}"),
+            LdVxDt(x) => write!(f, "LD V{x:X}, DT"),
+            LdVxK(x) => write!(f, "LD V{x:X}, K"),
+            LdDtVx(x) => write!(f, "LD DT, V{x:X}"),
+            LdStVx(x) => write!(f, "LD ST, V{x:X}"),
+            AddI(x) => write!(f, "ADD I, V{x:X}"),
+            LdF(x) => write!(f, "LD F, V{x:X}"),
+            LdHf(x) => write!(f, "LD HF, V{x:X}"),
+            LdB(x) => write!(f, "LD B, V{x:X}"),
+            LdIVx(x) => write!(f, "LD [I], V{x:X}"),
+            LdVxI(x) => write!(f, "LD V{x:X}, [I]"),
+            LdRVx(x) => write!(f, "LD R, V{x:X}"),
+            LdVxR(x) => write!(f, "LD V{x:X}, R"),
+            LdILong(nnnn) => write!(f, "LD I, LONG {nnnn:#06X}"),
+            Plane(n) => write!(f, "PLANE {n}"),
+            Audio => write!(f, "AUDIO"),
+            Pitch(x) => write!(f, "PITCH V{x:X}"),
+        }
+    }
+}
+
+impl Instruction {
+    /// Size in bytes: 4 for `F000 NNNN`, 2 for everything else.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(self) -> usize {
+        match self {
+            Instruction::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Instruction::LdILong(nnnn) = self {
+            bytes.extend_from_slice(&nnnn.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// The first opcode word. For `F000 NNNN` this is `F000`; the operand
+    /// is only included by `to_bytes`.
+    pub fn encode(self) -> u16 {
+        use Instruction::*;
+
+        let xy = |op: u16, x: u8, y: u8, n: u16| op | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xnn = |op: u16, x: u8, nn: u8| op | (x as u16) << 8 | nn as u16;
+        match self {
+            ScrollDown(n) => 0x00C0 | n as u16,
+            ScrollUp(n) => 0x00D0 | n as u16,
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            Low => 0x00FE,
+            High => 0x00FF,
+            Jp(nnn) => 0x1000 | nnn,
+            Call(nnn) => 0x2000 | nnn,
+            SeImm(x, nn) => xnn(0x3000, x, nn),
+            SneImm(x, nn) => xnn(0x4000, x, nn),
+            SeReg(x, y) => xy(0x5000, x, y, 0x0),
+            SaveRange(x, y) => xy(0x5000, x, y, 0x2),
+            LoadRange(x, y) => xy(0x5000, x, y, 0x3),
+            LdImm(x, nn) => xnn(0x6000, x, nn),
+            AddImm(x, nn) => xnn(0x7000, x, nn),
+            LdReg(x, y) => xy(0x8000, x, y, 0x0),
+            Or(x, y) => xy(0x8000, x, y, 0x1),
+            And(x, y) => xy(0x8000, x, y, 0x2),
+            Xor(x, y) => xy(0x8000, x, y, 0x3),
+            AddReg(x, y) => xy(0x8000, x, y, 0x4),
+            Sub(x, y) => xy(0x8000, x, y, 0x5),
+            Shr(x, y) => xy(0x8000, x, y, 0x6),
+            Subn(x, y) => xy(0x8000, x, y, 0x7),
+            Shl(x, y) => xy(0x8000, x, y, 0xE),
+            SneReg(x, y) => xy(0x9000, x, y, 0x0),
+            LdI(nnn) => 0xA000 | nnn,
+            JpV0(nnn) => 0xB000 | nnn,
+            Rnd(x, nn) => xnn(0xC000, x, nn),
+            Drw(x, y, n) => xy(0xD000, x, y, n as u16),
+            Skp(x) => xnn(0xE000, x, 0x9E),
+            Sknp(x) => xnn(0xE000, x, 0xA1),
+            LdVxDt(x) => xnn(0xF000, x, 0x07),
+            LdVxK(x) => xnn(0xF000, x, 0x0A),
+            LdDtVx(x) => xnn(0xF000, x, 0x15),
+            LdStVx(x) => xnn(0xF000, x, 0x18),
+            AddI(x) => xnn(0xF000, x, 0x1E),
+            LdF(x) => xnn(0xF000, x, 0x29),
+            LdHf(x) => xnn(0xF000, x, 0x30),
+            LdB(x) => xnn(0xF000, x, 0x33),
+            LdIVx(x) => xnn(0xF000, x, 0x55),
+            LdVxI(x) => xnn(0xF000, x, 0x65),
+            LdRVx(x) => xnn(0xF000, x, 0x75),
+            LdVxR(x) => xnn(0xF000, x, 0x85),
+            LdILong(_) => 0xF000,
+            Plane(n) => xnn(0xF000, n, 0x01),
+            Audio => 0xF002,
+            Pitch(x) => xnn(0xF000, x, 0x3A),
+        }
+    }
+
+    pub fn is_supported(self, platform: Platform) -> bool {
+        use Instruction::*;
+
+        match self {
+            ScrollDown(_) | ScrollRight | ScrollLeft | Exit | Low | High | LdHf(_) | LdRVx(_)
+            | LdVxR(_) => platform != Platform::Chip8,
+            ScrollUp(_) | SaveRange(..) | LoadRange(..) | LdILong(_) | Plane(_) | Audio
+            | Pitch(_) => platform == Platform::XoChip,
+            _ => true,
+        }
+    }
+
+    /// Whether the instruction conditionally skips the next one.
+    pub fn is_skip(self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            SeImm(..) | SneImm(..) | SeReg(..) | SneReg(..) | Skp(_) | Sknp(_)
+        )
+    }
+
+    /// Machine cycles the COSMAC VIP interpreter spends on the instruction,
+    /// fetch and decode included, given the state `chip8` is in before
+    /// executing it. A skip that is taken costs `VIP_SKIP_CYCLES` more.
+    /// Instructions the VIP does not have are charged like their nearest
+    /// equivalent.
+    pub fn vip_cycles(self, chip8: &CHIP8) -> u32 {
+        use Instruction::*;
+
+        const FETCH: u32 = 68;
+        FETCH
+            + match self {
+                Cls | Low | High => 1024,
+                ScrollDown(n) | ScrollUp(n) => 64 * n as u32,
+                ScrollRight | ScrollLeft => 512,
+                Ret => 10,
+                Jp(_) | LdI(_) => 12,
+                Call(_) => 26,
+                JpV0(_) => 22,
+                SeImm(..) | SneImm(..) | LdVxDt(_) | LdDtVx(_) | LdStVx(_) | AddImm(..) => 10,
+                SeReg(..) | SneReg(..) | Skp(_) | Sknp(_) => 14,
+                LdImm(..) | Exit | Plane(_) | Pitch(_) => 6,
+                LdReg(..) | Or(..) | And(..) | Xor(..) | AddReg(..) | Sub(..) | Shr(..)
+                | Subn(..) | Shl(..) => 44,
+                Rnd(..) => 36,
+                // Sprites not aligned to a byte boundary are shifted across
+                // two bytes per row.
+                Drw(x, _, n) => {
+                    let row = if chip8.vx_reg[x as usize].is_multiple_of(8) {
+                        22
+                    } else {
+                        34
+                    };
+                    let rows = if n == 0 { 16 } else { n as u32 };
+                    26 + rows * row
+                }
+                LdVxK(_) => 8,
+                AddI(_) => 18,
+                LdF(_) | LdHf(_) => 20,
+                LdB(x) => {
+                    let value = chip8.vx_reg[x as usize] as u32;
+                    84 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+                }
+                LdIVx(x) | LdVxI(x) | LdRVx(x) | LdVxR(x) => 14 + 14 * (x as u32 + 1),
+                SaveRange(x, y) | LoadRange(x, y) => 14 + 14 * (x.abs_diff(y) as u32 + 1),
+                LdILong(_) => 24,
+                Audio => 14 + 14 * 16,
+            }
+    }
+
+    /// Executes the instruction on `chip8`, whose `pc` must already point
+    /// past it, as it does right after `update` fetches an opcode.
+    pub fn execute(self, chip8: &mut CHIP8, input: &CHIP8Input) -> Result<CHIP8Output, ExecError> {
+        use Instruction::*;
+
+        let address = (chip8.pc as u16).wrapping_sub(self.len() as u16);
+        let (width, height) = chip8.display_size();
+        let v = &mut chip8.vx_reg;
+        let mut out = CHIP8Output::default();
+        match self {
+            ScrollDown(n) | ScrollUp(n) => {
+                let n = n as usize;
+                for plane in chip8.selected_planes_mut() {
+                    let rows = &mut plane[..height];
+                    if let ScrollDown(_) = self {
+                        rows.rotate_right(n);
+                        rows[..n].fill([false; HIRES_WIDTH]);
+                    } else {
+                        rows.rotate_left(n);
+                        rows[height - n..].fill([false; HIRES_WIDTH]);
+                    }
+                }
+                out.request_redraw = true;
+            }
+            ScrollRight | ScrollLeft => {
+                for plane in chip8.selected_planes_mut() {
+                    for row in plane[..height].iter_mut() {
+                        let row = &mut row[..width];
+                        if self == ScrollRight {
+                            row.rotate_right(4);
+                            row[..4].fill(false);
+                        } else {
+                            row.rotate_left(4);
+                            row[width - 4..].fill(false);
+                        }
+                    }
+                }
+                out.request_redraw = true;
+            }
+            Exit => {
+                chip8.pc -= 2;
+                out.exit_requested = true;
+            }
+            Low | High => {
+                chip8.hires = self == High;
+                chip8.planes = [[[false; HIRES_WIDTH]; HIRES_HEIGHT]; PLANES];
+                out.request_redraw = true;
+            }
+            Cls => {
+                for plane in chip8.selected_planes_mut() {
+                    *plane = [[false; HIRES_WIDTH]; HIRES_HEIGHT];
+                }
+                out.request_redraw = true;
+            }
+            Ret => chip8.pc = chip8.pop_stack(address)? as usize,
+            Jp(nnn) => chip8.pc = nnn as usize,
+            Call(nnn) => {
+                chip8.push_stack(address, chip8.pc as u16)?;
+                chip8.pc = nnn as usize;
+            }
+            SeImm(x, nn) => {
+                if v[x as usize] == nn {
+                    chip8.skip();
+                }
+            }
+            SneImm(x, nn) => {
+                if v[x as usize] != nn {
+                    chip8.skip();
+                }
+            }
+            SeReg(x, y) => {
+                if v[x as usize] == v[y as usize] {
+                    chip8.skip();
+                }
+            }
+            SaveRange(x, y) | LoadRange(x, y) => {
+                // Registers are walked in the given order, which may be descending.
+                let (x, y) = (x as usize, y as usize);
+                let count = x.abs_diff(y);
+                for offset in 0..=count {
+                    let register = if x <= y { x + offset } else { x - offset };
+                    let index = chip8.i_reg as usize + offset;
+                    if let SaveRange(..) = self {
+                        chip8.write_ram(address, index, chip8.vx_reg[register])?;
+                    } else {
+                        chip8.vx_reg[register] = chip8.read_ram(address, index)?;
+                    }
+                }
+            }
+            LdImm(x, nn) => v[x as usize] = nn,
+            AddImm(x, nn) => v[x as usize] = v[x as usize].wrapping_add(nn),
+            LdReg(x, y) => v[x as usize] = v[y as usize],
+            Or(x, y) | And(x, y) | Xor(x, y) => {
+                let (vx, vy) = (v[x as usize], v[y as usize]);
+                v[x as usize] = match self {
+                    Or(..) => vx | vy,
+                    And(..) => vx & vy,
+                    _ => vx ^ vy,
+                };
+                if chip8.quirks.vf_reset {
+                    v[0xF] = 0;
+                }
+            }
+            AddReg(x, y) => {
+                let overflowed;
+                (v[x as usize], overflowed) = v[x as usize].overflowing_add(v[y as usize]);
+                v[0xF] = if overflowed { 1 } else { 0 };
+            }
+            Sub(x, y) => {
+                let (f, s) = (v[x as usize], v[y as usize]);
+                v[x as usize] = f.wrapping_sub(s);
+                v[0xF] = if f > s { 1 } else { 0 };
+            }
+            Shr(x, y) => {
+                if chip8.quirks.i_8xy6 {
+                    v[x as usize] = v[y as usize];
+                }
+                let bit = v[x as usize] & 0x1;
+                v[x as usize] >>= 1;
+                v[0xF] = bit;
+            }
+            Subn(x, y) => {
+                let (f, s) = (v[y as usize], v[x as usize]);
+                v[x as usize] = f.wrapping_sub(s);
+                v[0xF] = if f > s { 1 } else { 0 };
+            }
+            Shl(x, y) => {
+                if chip8.quirks.i_8xye {
+                    v[x as usize] = v[y as usize];
+                }
+                let bit = v[x as usize] >> 7;
+                v[x as usize] <<= 1;
+                v[0xF] = bit;
+            }
+            SneReg(x, y) => {
+                if v[x as usize] != v[y as usize] {
+                    chip8.skip();
+                }
+            }
+            LdI(nnn) => chip8.i_reg = nnn,
+            JpV0(nnn) => {
+                let offset = if chip8.quirks.bnnn {
+                    v[0]
+                } else {
+                    v[(nnn >> 8) as usize]
+                };
+                chip8.pc = (nnn + offset as u16) as usize;
+            }
+            Rnd(x, nn) => {
+                let random = chip8.rng.next_u32() as u8;
+                v[x as usize] = random & nn;
+            }
+            Drw(x, y, n) => {
+                if chip8.quirks.display_wait && !chip8.hires {
+                    if !chip8.vblank {
+                        chip8.pc -= 2;
+                        return Ok(out);
+                    }
+                    chip8.vblank = false;
+                }
+                out.request_redraw = true;
+                let origin_x = v[x as usize] as usize % width;
+                let origin_y = v[y as usize] as usize % height;
+                // DXY0 draws a 16x16 sprite made of two bytes per row.
+                let (rows, columns) = if n == 0 && chip8.platform != Platform::Chip8 {
+                    (16, 16)
+                } else {
+                    (n as usize, 8)
+                };
+                let wrap = chip8.quirks.wrap;
+                let (mut collided_rows, mut clipped_rows) = (0, 0);
+
+                // With several planes selected, each one takes the next sprite
+                // in memory.
+                let mut i = chip8.i_reg as usize;
+                for plane in 0..PLANES {
+                    if chip8.selected_planes & (1 << plane) == 0 {
+                        continue;
+                    }
+                    for row in 0..rows {
+                        let mut py = origin_y + row;
+                        if py >= height {
+                            if !wrap {
+                                clipped_rows += rows - row;
+                                break;
+                            }
+                            py %= height;
+                        }
+                        let mut collided = false;
+                        let bits = if columns == 16 {
+                            u16::from_be_bytes([
+                                chip8.read_ram(address, i + row * 2)?,
+                                chip8.read_ram(address, i + row * 2 + 1)?,
+                            ])
+                        } else {
+                            (chip8.read_ram(address, i + row)? as u16) << 8
+                        };
+                        for column in 0..columns {
+                            let mut px = origin_x + column;
+                            if px >= width {
+                                if !wrap {
+                                    break;
+                                }
+                                px %= width;
+                            }
+                            if bits & (0x8000 >> column) != 0 {
+                                let pixel = &mut chip8.planes[plane][py][px];
+                                collided |= *pixel;
+                                *pixel = !*pixel;
+                            }
+                        }
+                        collided_rows += collided as usize;
+                    }
+                    i += rows * columns / 8;
+                }
+                chip8.vx_reg[0xF] = if chip8.quirks.vf_rows && chip8.hires {
+                    (collided_rows + clipped_rows) as u8
+                } else {
+                    (collided_rows > 0) as u8
+                };
+            }
+            Skp(x) => {
+                if input.pressed_keys[(v[x as usize] & 0x0F) as usize] {
+                    chip8.skip();
+                }
+            }
+            Sknp(x) => {
+                if !input.pressed_keys[(v[x as usize] & 0x0F) as usize] {
+                    chip8.skip();
+                }
+            }
+            LdVxDt(x) => v[x as usize] = chip8.delay_timer,
+            LdVxK(x) => match input.released_key {
+                Some(key) => v[x as usize] = key as u8,
+                None => chip8.pc -= 2,
+            },
+            LdDtVx(x) => chip8.delay_timer = v[x as usize],
+            LdStVx(x) => chip8.sound_timer = v[x as usize],
+            AddI(x) => {
+                let overflowing;
+                (chip8.i_reg, overflowing) = chip8.i_reg.overflowing_add(v[x as usize] as u16);
+                if !chip8.quirks.fx1e {
+                    v[0xF] = if overflowing { 1 } else { 0 }
+                }
+            }
+            LdF(x) => chip8.i_reg = chip8.layout.font_address + ((v[x as usize] & 0x0F) as u16) * 5,
+            LdHf(x) => {
+                let big_font = chip8.layout.font_address + FONT_SIZE;
+                chip8.i_reg = big_font + ((v[x as usize] & 0x0F) as u16) * 10;
+            }
+            LdB(x) => {
+                let num = v[x as usize];
+                let i = chip8.i_reg as usize;
+                chip8.write_ram(address, i, num / 100)?;
+                chip8.write_ram(address, i + 1, (num / 10) % 10)?;
+                chip8.write_ram(address, i + 2, num % 10)?;
+            }
+            LdIVx(x) => {
+                for idx in 0..=x as usize {
+                    let index = chip8.i_reg as usize + if !chip8.quirks.fx55 { idx } else { 0 };
+                    chip8.write_ram(address, index, chip8.vx_reg[idx])?;
+                    if chip8.quirks.fx55 {
+                        chip8.i_reg = chip8.i_reg.wrapping_add(1);
+                    }
+                }
+            }
+            LdVxI(x) => {
+                for idx in 0..=x as usize {
+                    let index = chip8.i_reg as usize + if !chip8.quirks.fx65 { idx } else { 0 };
+                    chip8.vx_reg[idx] = chip8.read_ram(address, index)?;
+                    if chip8.quirks.fx65 {
+                        chip8.i_reg = chip8.i_reg.wrapping_add(1);
+                    }
+                }
+            }
+            LdRVx(x) => {
+                let x = x as usize;
+                chip8.rpl_flags[..=x].copy_from_slice(&v[..=x]);
+            }
+            LdVxR(x) => {
+                let x = x as usize;
+                v[..=x].copy_from_slice(&chip8.rpl_flags[..=x]);
+            }
+            LdILong(nnnn) => chip8.i_reg = nnnn,
+            Plane(n) => chip8.selected_planes = n & 0b11,
+            Audio => {
+                for idx in 0..chip8.audio_pattern.len() {
+                    chip8.audio_pattern[idx] =
+                        chip8.read_ram(address, chip8.i_reg as usize + idx)?;
+                }
+            }
+            Pitch(x) => chip8.pitch = v[x as usize],
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CHIP8Input, Profile, Quirks, CHIP8, DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_HEIGHT,
+        HIRES_WIDTH,
+    };
+
+    /// Runs `program` from the start with `setup` applied to a fresh
+    /// machine first.
+    fn run(program: &[u8], quirks: Quirks, setup: impl FnOnce(&mut CHIP8)) -> CHIP8 {
+        let mut chip8 = CHIP8::new(Platform::Chip8, quirks);
+        chip8.load_program(program).unwrap();
+        setup(&mut chip8);
+        for _ in 0..program.len() / 2 {
+            chip8.update(CHIP8Input::default()).unwrap();
+        }
+        chip8
+    }
+
+    /// `run` on SUPER-CHIP with the default quirks.
+    fn run_superchip(program: &[u8], setup: impl FnOnce(&mut CHIP8)) -> CHIP8 {
+        let mut chip8 = CHIP8::new(Platform::SuperChip, Quirks::default());
+        chip8.load_program(program).unwrap();
+        setup(&mut chip8);
+        for _ in 0..program.len() / 2 {
+            chip8.update(CHIP8Input::default()).unwrap();
+        }
+        chip8
+    }
+
+    #[test]
+    fn shifts_put_the_bit_shifted_out_in_vf() {
+        // 8016: v0 >>= v1
+        let chip8 = run(&[0x80, 0x16], Quirks::default(), |c| c.vx_reg[0] = 0b101);
+        assert_eq!((chip8.vx_reg[0], chip8.vx_reg[0xF]), (0b10, 1));
+        let chip8 = run(&[0x80, 0x16], Quirks::default(), |c| c.vx_reg[0] = 0b100);
+        assert_eq!((chip8.vx_reg[0], chip8.vx_reg[0xF]), (0b10, 0));
+
+        // 801E: v0 <<= v1
+        let chip8 = run(&[0x80, 0x1E], Quirks::default(), |c| c.vx_reg[0] = 0x81);
+        assert_eq!((chip8.vx_reg[0], chip8.vx_reg[0xF]), (0x02, 1));
+        let chip8 = run(&[0x80, 0x1E], Quirks::default(), |c| c.vx_reg[0] = 0x08);
+        assert_eq!((chip8.vx_reg[0], chip8.vx_reg[0xF]), (0x10, 0));
+    }
+
+    #[test]
+    fn every_opcode_encodes_back_to_itself() {
+        for opcode in 0..=u16::MAX {
+            if let Ok(instruction) = decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{instruction}");
+                assert_eq!(decode_at(&instruction.to_bytes(), 0), Ok(instruction));
+            }
+        }
+    }
+
+    #[test]
+    fn long_loads_take_four_bytes() {
+        let bytes = Instruction::LdILong(0x1234).to_bytes();
+        assert_eq!(bytes, [0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(decode_at(&bytes, 0), Ok(Instruction::LdILong(0x1234)));
+        assert!(decode_at(&bytes[..2], 0).is_err());
+    }
+
+    #[test]
+    fn shift_quirks_shift_vy_into_vx() {
+        let quirks = Quirks {
+            i_8xy6: true,
+            i_8xye: true,
+            ..Quirks::default()
+        };
+        // 8016: v0 >>= v1, 823E: v2 <<= v3
+        let chip8 = run(&[0x80, 0x16, 0x82, 0x3E], quirks, |c| {
+            c.vx_reg[1] = 0b11;
+            c.vx_reg[3] = 0x40;
+        });
+        assert_eq!(chip8.vx_reg[..4], [0b1, 0b11, 0x80, 0x40]);
+        assert_eq!(chip8.vx_reg[0xF], 0);
+    }
+
+    #[test]
+    fn save_quirk_moves_i_past_the_registers() {
+        // A300: i := 0x300, F255: save v2
+        let program = [0xA3, 0x00, 0xF2, 0x55];
+        let setup = |c: &mut CHIP8| c.vx_reg[..3].copy_from_slice(&[7, 8, 9]);
+        let chip8 = run(&program, Quirks::default(), setup);
+        assert_eq!(
+            (&chip8.ram[0x300..0x303], chip8.i_reg),
+            (&[7, 8, 9][..], 0x300)
+        );
+        let quirks = Quirks {
+            fx55: true,
+            ..Quirks::default()
+        };
+        let chip8 = run(&program, quirks, setup);
+        assert_eq!(
+            (&chip8.ram[0x300..0x303], chip8.i_reg),
+            (&[7, 8, 9][..], 0x303)
+        );
+    }
+
+    #[test]
+    fn jump_quirk_chooses_between_v0_and_vx() {
+        // B210: jump0 0x210
+        let setup = |c: &mut CHIP8| {
+            c.vx_reg[0] = 4;
+            c.vx_reg[2] = 8;
+        };
+        let chip8 = run(&[0xB2, 0x10], Quirks::default(), setup);
+        assert_eq!(chip8.pc, 0x218);
+        let quirks = Quirks {
+            bnnn: true,
+            ..Quirks::default()
+        };
+        assert_eq!(run(&[0xB2, 0x10], quirks, setup).pc, 0x214);
+    }
+
+    #[test]
+    fn xo_chip_skips_step_over_both_words_of_a_long_load() {
+        // 3000: skip if v0 == 0, F000 1234: i := long 0x1234, 6105: v1 := 5
+        let program = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x61, 0x05];
+        let mut chip8 = CHIP8::new(Platform::XoChip, Quirks::default());
+        chip8.load_program(&program).unwrap();
+        chip8.update(CHIP8Input::default()).unwrap();
+        assert_eq!(chip8.pc, 0x206);
+        chip8.update(CHIP8Input::default()).unwrap();
+        assert_eq!((chip8.vx_reg[1], chip8.i_reg), (5, 0));
+
+        // Other platforms know nothing of F000 and skip a single word.
+        let chip8 = run(&program[..2], Quirks::default(), |_| {});
+        assert_eq!(chip8.pc, 0x204);
+    }
+
+    #[test]
+    fn xo_chip_draws_a_sprite_per_selected_plane() {
+        // F301: plane 3, A20A: i := 0x20A, D001, F201: plane 2, D001
+        let program = [
+            0xF3, 0x01, 0xA2, 0x0A, 0xD0, 0x01, 0xF2, 0x01, 0xD0, 0x01, 0x80, 0x40,
+        ];
+        let mut chip8 = CHIP8::new(Platform::XoChip, Quirks::default());
+        chip8.load_program(&program).unwrap();
+        for _ in 0..3 {
+            chip8.update(CHIP8Input::default()).unwrap();
+        }
+        // Both planes selected: the first takes 0x80, the second 0x40.
+        assert!(chip8.planes[0][0][0] && !chip8.planes[0][0][1]);
+        assert!(!chip8.planes[1][0][0] && chip8.planes[1][0][1]);
+
+        for _ in 0..2 {
+            chip8.update(CHIP8Input::default()).unwrap();
+        }
+        // Only the second plane selected: it takes the first sprite and
+        // plane 1 is left alone.
+        assert!(chip8.planes[0][0][0] && !chip8.planes[0][0][1]);
+        assert!(chip8.planes[1][0][0] && chip8.planes[1][0][1]);
+        assert_eq!(chip8.vx_reg[0xF], 0);
+    }
+
+    #[test]
+    fn legacy_superchip_counts_collided_and_clipped_rows_in_hires() {
+        // D012: draw two rows at (v0, v1), the second off the bottom
+        let draw = |quirks: Quirks| {
+            let mut chip8 = CHIP8::new(Platform::SuperChip, quirks);
+            chip8.load_program(&[0xD0, 0x12]).unwrap();
+            chip8.hires = true;
+            chip8.vblank = false;
+            chip8.vx_reg[1] = 63;
+            chip8.i_reg = 0x300;
+            chip8.ram[0x300..0x302].copy_from_slice(&[0x80, 0x80]);
+            chip8.planes[0][63][0] = true;
+            chip8.update(CHIP8Input::default()).unwrap();
+            chip8
+        };
+        let modern = draw(Profile::SuperChipModern.quirks());
+        assert_eq!((modern.pc, modern.vx_reg[0xF]), (0x202, 1));
+        // The vblank wait only applies in lo-res.
+        let legacy = draw(Profile::SuperChipLegacy.quirks());
+        assert_eq!((legacy.pc, legacy.vx_reg[0xF]), (0x202, 2));
+    }
+
+    #[test]
+    fn superchip_switches_resolution_and_draws_16x16_sprites() {
+        // 00FF: high, 6078: v0 := 120, 6138: v1 := 56, A20A: i := 0x20A,
+        // D010: 16x16 sprite clipped at the bottom right corner
+        let program = [0x00, 0xFF, 0x60, 0x78, 0x61, 0x38, 0xA2, 0x0A, 0xD0, 0x10];
+        let mut chip8 = run_superchip(&program, |c| c.ram[0x20A..0x22A].fill(0xFF));
+        assert_eq!(chip8.display_size(), (HIRES_WIDTH, HIRES_HEIGHT));
+        let lit: Vec<(usize, usize)> = (0..HIRES_HEIGHT)
+            .flat_map(|y| (0..HIRES_WIDTH).map(move |x| (x, y)))
+            .filter(|&(x, y)| chip8.planes[0][y][x])
+            .collect();
+        assert_eq!(lit.len(), 64);
+        assert!(lit.iter().all(|&(x, y)| x >= 120 && y >= 56));
+        assert_eq!(chip8.vx_reg[0xF], 0);
+
+        // 00FE: low clears the screen
+        chip8.ram[0x200..0x202].copy_from_slice(&[0x00, 0xFE]);
+        chip8.pc = 0x200;
+        chip8.update(CHIP8Input::default()).unwrap();
+        assert_eq!(chip8.display_size(), (DISPLAY_WIDTH, DISPLAY_HEIGHT));
+        assert!(chip8.planes[0].iter().flatten().all(|&on| !on));
+    }
+
+    #[test]
+    fn superchip_scrolls_the_display() {
+        // 00C3: scroll down 3, 00FB: scroll right 4, 00FC: scroll left 4
+        let lit = |chip8: &CHIP8| {
+            let (width, height) = chip8.display_size();
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .filter(|&(x, y)| chip8.planes[0][y][x])
+                .collect::<Vec<_>>()
+        };
+        let setup = |c: &mut CHIP8| {
+            c.hires = true;
+            c.planes[0][0][0] = true;
+            c.planes[0][0][HIRES_WIDTH - 1] = true;
+        };
+        let chip8 = run_superchip(&[0x00, 0xC3, 0x00, 0xFB], setup);
+        // The right edge scrolls off instead of wrapping round.
+        assert_eq!(lit(&chip8), [(4, 3)]);
+        let chip8 = run_superchip(&[0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC], setup);
+        assert_eq!(lit(&chip8), [(0, 3)]);
+    }
+
+    #[test]
+    fn superchip_big_digits_and_flag_registers() {
+        // F030: i := bighex v0
+        let chip8 = run_superchip(&[0xF0, 0x30], |c| c.vx_reg[0] = 7);
+        let digit = chip8.i_reg as usize;
+        assert_eq!(chip8.ram[digit..digit + 10], crate::font::BIG_FONT[70..80]);
+
+        // F175: saveflags v1, 6000: v0 := 0, 6100: v1 := 0, F185: loadflags v1
+        let program = [0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x85];
+        let chip8 = run_superchip(&program, |c| c.vx_reg[..3].copy_from_slice(&[5, 9, 2]));
+        assert_eq!(chip8.rpl_flags[..3], [5, 9, 0]);
+        assert_eq!(chip8.vx_reg[..3], [5, 9, 2]);
+    }
+}
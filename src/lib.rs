@@ -1,37 +1,559 @@
+#[cfg(feature = "audio")]
+mod audio;
 mod display;
-use display::Display;
+mod state;
 
 pub mod app;
+pub mod asm;
+pub mod database;
+pub mod debug;
+pub mod disasm;
+pub mod expr;
+pub mod font;
+pub mod gdb;
+pub mod instruction;
+pub mod rewind;
+pub mod rom;
+pub mod tui;
+
+pub use debug::{Breakpoints, Status, StopReason};
+pub use font::Font;
+pub use instruction::{decode, decode_at, DecodeError, Instruction};
+pub use state::StateError;
+
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// Large enough for hi-res mode. In lo-res mode only the top-left
+/// `DISPLAY_WIDTH` x `DISPLAY_HEIGHT` pixels are used, see
+/// `CHIP8::display_size`.
+pub type Framebuffer = [[bool; HIRES_WIDTH]; HIRES_HEIGHT];
+
+/// XO-CHIP draws to two bitplanes. Other platforms only use the first.
+pub const PLANES: usize = 2;
+
+const FONT_SIZE: u16 = font::FONT_SIZE as u16;
+
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 12;
+
+/// Machine cycles per 60Hz frame the COSMAC VIP leaves to the interpreter
+/// once the display DMA and the timer interrupt have taken theirs.
+pub const VIP_CYCLES_PER_FRAME: u32 = 2600;
+/// Extra machine cycles a taken skip costs on the COSMAC VIP.
+pub const VIP_SKIP_CYCLES: u32 = 8;
 
 pub struct CHIP8 {
     pc: usize,
-    ram: [u8; 4096],
+    ram: Vec<u8>,
+    layout: MemoryLayout,
+    /// Return addresses. With `StackConfig::in_ram` the copies in memory
+    /// are the ones returned to; this then only tracks the depth.
     stack: Vec<u16>,
-    pub delay_timer: u8,
-    pub sound_timer: u8,
+    stack_config: StackConfig,
+    delay_timer: u8,
+    sound_timer: u8,
     i_reg: u16,
     vx_reg: [u8; 16],
-    old_behaviour_conf: OldBehaviourConfig,
+    planes: [Framebuffer; PLANES],
+    selected_planes: u8,
+    hires: bool,
+    rpl_flags: [u8; 16],
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    rng: Random,
+    rom_hash: u64,
+    platform: Platform,
+    quirks: Quirks,
+    /// Set by every 60Hz timer tick and consumed by DXYN when
+    /// `Quirks::display_wait` is on.
+    vblank: bool,
+    /// VIP cycles the last frame ran over its budget, taken from the next.
+    cycle_overrun: u32,
+    breakpoints: Breakpoints,
+    /// Where the last breakpoint stopped, so that resuming executes the
+    /// instruction there instead of stopping again.
+    resume_from: Option<usize>,
+    /// The first watched memory access of the current instruction.
+    watch_hit: Option<StopReason>,
+}
+
+/// The random source behind CXNN. A seeded one is the same generator as
+/// `StdRng`, and its position in the stream goes into save states.
+enum Random {
+    Seeded { seed: u64, rng: Box<ChaCha12Rng> },
+    Custom(Box<dyn RngCore + Send>),
+}
+
+impl Random {
+    fn seeded(seed: u64) -> Self {
+        Random::Seeded {
+            seed,
+            rng: Box::new(ChaCha12Rng::seed_from_u64(seed)),
+        }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Random::Seeded { rng, .. } => rng.next_u32(),
+            Random::Custom(rng) => rng.next_u32(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn memory_layout(self) -> MemoryLayout {
+        MemoryLayout {
+            memory_size: match self {
+                Platform::Chip8 | Platform::SuperChip => 0x1000,
+                Platform::XoChip => 0x10000,
+            },
+            ..MemoryLayout::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryLayout {
+    /// Where `load_program` puts the ROM and execution starts.
+    pub program_start: u16,
+    /// Where the 4x5 hex font is stored. The SUPER-CHIP 8x10 font follows
+    /// right after it.
+    pub font_address: u16,
+    pub memory_size: usize,
+    /// Sound timer value at power-on, giving the short start-up beep some
+    /// interpreters had.
+    pub initial_sound_timer: u8,
+}
+
+impl Default for MemoryLayout {
+    fn default() -> Self {
+        MemoryLayout {
+            program_start: 0x200,
+            font_address: 0x50,
+            memory_size: 0x1000,
+            initial_sound_timer: 5,
+        }
+    }
+}
+
+impl MemoryLayout {
+    /// Checks that the font and at least one instruction fit in memory.
+    pub fn validate(&self, platform: Platform) -> Result<(), String> {
+        let font_end = self.font_end(platform);
+        if self.memory_size > 0x10000 {
+            return Err(format!(
+                "memory size {:#X} is larger than 64KiB",
+                self.memory_size
+            ));
+        }
+        if font_end > self.memory_size {
+            return Err(format!(
+                "font at {:#X} does not fit in {:#X} bytes of memory",
+                self.font_address, self.memory_size
+            ));
+        }
+        if self.font_address < self.program_start && font_end > self.program_start as usize {
+            return Err(format!(
+                "font at {:#X} runs into the program at {:#X}",
+                self.font_address, self.program_start
+            ));
+        }
+        if self.program_start as usize + 2 > self.memory_size {
+            return Err(format!(
+                "program start {:#X} is outside {:#X} bytes of memory",
+                self.program_start, self.memory_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// End of the hex font, and of the SUPER-CHIP big font after it.
+    fn font_end(&self, platform: Platform) -> usize {
+        self.font_address as usize
+            + FONT_SIZE as usize * if platform == Platform::Chip8 { 1 } else { 3 }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// A named combination of platform and quirks matching a real
+/// implementation, selected with `--platform`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Plain CHIP-8 with the behaviour most modern ROMs expect.
+    Chip8,
+    CosmacVip,
+    Chip48,
+    /// SUPER-CHIP 1.1 as it ran on the HP48.
+    SuperChipLegacy,
+    /// SUPER-CHIP as implemented by Octo and most modern interpreters.
+    SuperChipModern,
+    XoChip,
+}
+
+impl Profile {
+    pub fn from_name(name: &str) -> Option<Profile> {
+        Some(match name.to_lowercase().as_str() {
+            "chip8" | "chip-8" => Profile::Chip8,
+            "vip" | "cosmac-vip" => Profile::CosmacVip,
+            "chip48" | "chip-48" => Profile::Chip48,
+            "schip-legacy" | "schip1.1" => Profile::SuperChipLegacy,
+            "schip" | "superchip" | "schip-modern" => Profile::SuperChipModern,
+            "xochip" | "xo-chip" | "xo" => Profile::XoChip,
+            _ => return None,
+        })
+    }
+
+    pub fn platform(self) -> Platform {
+        match self {
+            Profile::Chip8 | Profile::CosmacVip | Profile::Chip48 => Platform::Chip8,
+            Profile::SuperChipLegacy | Profile::SuperChipModern => Platform::SuperChip,
+            Profile::XoChip => Platform::XoChip,
+        }
+    }
+
+    /// Default speed, close to what the real implementation managed.
+    pub fn instructions_per_frame(self) -> usize {
+        match self {
+            Profile::Chip8 => DEFAULT_INSTRUCTIONS_PER_FRAME,
+            Profile::CosmacVip => 15,
+            Profile::Chip48 | Profile::SuperChipLegacy | Profile::SuperChipModern => 30,
+            Profile::XoChip => 100,
+        }
+    }
+
+    pub fn memory_layout(self) -> MemoryLayout {
+        match self {
+            // The VIP interpreter copied its font just below the program.
+            Profile::CosmacVip => MemoryLayout {
+                font_address: 0x1B0,
+                ..self.platform().memory_layout()
+            },
+            _ => self.platform().memory_layout(),
+        }
+    }
+
+    pub fn font(self) -> Font {
+        match self {
+            Profile::CosmacVip => Font::Vip,
+            _ => Font::Octo,
+        }
+    }
+
+    pub fn stack(self) -> StackConfig {
+        match self {
+            Profile::CosmacVip => StackConfig {
+                limit: 12,
+                in_ram: true,
+            },
+            _ => StackConfig::default(),
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Profile::Chip8 => Quirks::default(),
+            Profile::CosmacVip => Quirks {
+                fx65: true,
+                fx55: true,
+                i_8xy6: true,
+                i_8xye: true,
+                bnnn: true,
+                fx1e: true,
+                vf_reset: true,
+                wrap: false,
+                display_wait: true,
+                vf_rows: false,
+            },
+            Profile::Chip48 | Profile::SuperChipModern => Quirks {
+                fx1e: true,
+                ..Quirks::default()
+            },
+            Profile::SuperChipLegacy => Quirks {
+                fx1e: true,
+                display_wait: true,
+                vf_rows: true,
+                ..Quirks::default()
+            },
+            Profile::XoChip => Quirks {
+                fx65: true,
+                fx55: true,
+                i_8xy6: true,
+                i_8xye: true,
+                bnnn: true,
+                fx1e: true,
+                wrap: true,
+                ..Quirks::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct CHIP8Input {
     pub pressed_keys: [bool; 16],
     pub released_key: Option<InputKey>,
 }
 
+#[derive(Debug, Default)]
 pub struct CHIP8Output {
     pub request_redraw: bool,
+    /// Set when the program executed 00FD. The machine stays on that
+    /// instruction until the frontend stops it.
+    pub exit_requested: bool,
+    /// COSMAC VIP machine cycles spent, see `Instruction::vip_cycles`.
+    pub cycles: u32,
+    /// Whether a breakpoint or watchpoint stopped execution.
+    pub status: Status,
+    /// Messages from log points.
+    pub log: Vec<String>,
 }
 
-#[derive(Debug)]
-pub struct OldBehaviourConfig {
+/// Behaviour that differs between CHIP-8 implementations. Everything off
+/// is what most modern ROMs expect; see `Profile` for the combinations
+/// real interpreters used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// FX65 leaves I pointing past the last register loaded.
     pub fx65: bool,
+    /// FX55 leaves I pointing past the last register stored.
     pub fx55: bool,
+    /// 8XY6 shifts VY instead of VX.
     pub i_8xy6: bool,
+    /// 8XYE shifts VY instead of VX.
     pub i_8xye: bool,
+    /// BNNN jumps to NNN + V0 rather than XNN + VX.
     pub bnnn: bool,
+    /// FX1E leaves VF alone instead of setting it when I overflows 16 bits.
     pub fx1e: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    /// Sprites wrap around the screen edges instead of being clipped.
+    pub wrap: bool,
+    /// DXYN waits for the next 60Hz tick, drawing at most one sprite per
+    /// frame. Hi-res drawing never waits.
+    pub display_wait: bool,
+    /// In hi-res, DXYN sets VF to the number of sprite rows that collided
+    /// or were clipped off the bottom, as SUPER-CHIP 1.1 did.
+    pub vf_rows: bool,
+}
+
+impl Quirks {
+    /// Looks a quirk up by the name the command line uses for it.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name.to_lowercase().as_str() {
+            "fx65" => &mut self.fx65,
+            "fx55" => &mut self.fx55,
+            "8xy6" => &mut self.i_8xy6,
+            "8xye" => &mut self.i_8xye,
+            "bnnn" => &mut self.bnnn,
+            "fx1e" => &mut self.fx1e,
+            "vfreset" => &mut self.vf_reset,
+            "wrap" => &mut self.wrap,
+            "vblank" => &mut self.display_wait,
+            "vfrows" => &mut self.vf_rows,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecError {
+    UnknownOpcode { address: u16, opcode: u16 },
+    StackUnderflow { address: u16 },
+    StackOverflow { address: u16, limit: usize },
+    MemoryOutOfBounds { address: u16, index: usize },
+    PcOutOfRange { pc: usize },
+}
+
+impl std::fmt::Display for ExecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecError::UnknownOpcode { address, opcode } => {
+                write!(f, "unknown opcode {opcode:#06X} at {address:#05X}")
+            }
+            ExecError::StackUnderflow { address } => {
+                write!(f, "return with empty stack at {address:#05X}")
+            }
+            ExecError::StackOverflow { address, limit } => {
+                write!(
+                    f,
+                    "stack overflow at {address:#05X} (limit is {limit} calls)"
+                )
+            }
+            ExecError::MemoryOutOfBounds { address, index } => {
+                write!(
+                    f,
+                    "memory access out of bounds ({index:#X}) at {address:#05X}"
+                )
+            }
+            ExecError::PcOutOfRange { pc } => write!(f, "program counter out of range ({pc:#X})"),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    Empty,
+    TooLarge {
+        size: usize,
+        capacity: usize,
+    },
+    /// Instructions are two bytes long, so a ROM cannot end halfway
+    /// through one.
+    OddLength {
+        size: usize,
+    },
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Empty => write!(f, "ROM is empty"),
+            LoadError::TooLarge { size, capacity } => {
+                write!(f, "ROM is {size} bytes, but only {capacity} fit in memory")
+            }
+            LoadError::OddLength { size } => {
+                write!(
+                    f,
+                    "ROM is {size} bytes, an odd length for two-byte instructions"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// The COSMAC VIP kept its call stack just below this address, two bytes
+/// per entry, growing downwards.
+pub const VIP_STACK_ADDRESS: u16 = 0xED0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackConfig {
+    /// Deepest nesting of 2NNN calls. One more is a `StackOverflow`.
+    pub limit: usize,
+    /// Keep return addresses in memory below `VIP_STACK_ADDRESS` rather
+    /// than outside the machine, so ROMs can read and patch them.
+    pub in_ram: bool,
+}
+
+impl Default for StackConfig {
+    fn default() -> Self {
+        StackConfig {
+            limit: 16,
+            in_ram: false,
+        }
+    }
+}
+
+impl StackConfig {
+    /// Checks that the limit fits in a save state and, with the stack in
+    /// memory, that `limit` entries fit below `VIP_STACK_ADDRESS` without
+    /// reaching into the fonts or the first instruction of the program.
+    pub fn validate(&self, platform: Platform, layout: MemoryLayout) -> Result<(), String> {
+        if self.limit > u16::MAX as usize {
+            return Err(format!(
+                "stack limit {} is larger than {}",
+                self.limit,
+                u16::MAX
+            ));
+        }
+        if !self.in_ram {
+            return Ok(());
+        }
+        let end = VIP_STACK_ADDRESS as usize;
+        if end > layout.memory_size {
+            return Err(format!(
+                "the stack at {end:#X} is outside {:#X} bytes of memory",
+                layout.memory_size
+            ));
+        }
+        let Some(start) = end.checked_sub(2 * self.limit) else {
+            return Err(format!(
+                "{} stack entries do not fit below {end:#X}",
+                self.limit
+            ));
+        };
+        let overlaps = |from: usize, to: usize| start < to && from < end;
+        if overlaps(layout.font_address as usize, layout.font_end(platform)) {
+            return Err(format!(
+                "a stack of {} entries at {start:#X} overlaps the font at {:#X}",
+                self.limit, layout.font_address
+            ));
+        }
+        let program = layout.program_start as usize;
+        if overlaps(program, program + 2) {
+            return Err(format!(
+                "a stack of {} entries at {start:#X} overlaps the program start at {program:#X}",
+                self.limit
+            ));
+        }
+        Ok(())
+    }
+
+    /// Where the stack starts in memory when it is kept there.
+    fn ram_start(&self) -> Option<usize> {
+        self.in_ram
+            .then(|| VIP_STACK_ADDRESS as usize - 2 * self.limit)
+    }
+}
+
+/// The sound output: the 128-bit XO-CHIP audio pattern looped at the FX3A
+/// pitch while the sound timer runs. Other platforms keep the default
+/// pattern, a 500 Hz square wave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Buzzer {
+    pub pattern: [u8; 16],
+    pub pitch: u8,
+    pub on: bool,
+}
+
+impl Default for Buzzer {
+    fn default() -> Self {
+        Buzzer {
+            pattern: [0xF0; 16],
+            pitch: 64,
+            on: false,
+        }
+    }
+}
+
+impl Buzzer {
+    /// Playback rate of the pattern in bits per second. The default pitch
+    /// of 64 gives 4000 Hz.
+    pub fn bit_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+
+    /// Fills `out` with samples at `sample_rate` Hz, silence while the
+    /// buzzer is off. `phase` is the position in the pattern and carries
+    /// over between calls.
+    pub fn render(&self, out: &mut [f32], sample_rate: u32, phase: &mut f64) {
+        if !self.on {
+            out.fill(0.0);
+            return;
+        }
+        let step = self.bit_rate() / sample_rate as f64;
+        for sample in out.iter_mut() {
+            let bit = *phase as usize % 128;
+            let high = self.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            *sample = if high { 0.25 } else { -0.25 };
+            *phase = (*phase + step) % 128.0;
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -45,286 +567,771 @@ pub enum InputKey {
 }
 
 impl CHIP8 {
-    pub fn new(old_behaviour_conf: OldBehaviourConfig) -> Self {
-        let mut ram = [0; 4096];
-
-        ram[0x50..=0x9F].copy_from_slice(&[
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ]);
-
-        CHIP8 {
-            pc: 0x200,
+    pub fn new(platform: Platform, quirks: Quirks) -> Self {
+        Self::with_layout(platform, quirks, platform.memory_layout())
+            .expect("the platform's own layout is valid")
+    }
+
+    /// Panics if `layout` does not pass `MemoryLayout::validate`.
+    pub fn with_layout(
+        platform: Platform,
+        quirks: Quirks,
+        layout: MemoryLayout,
+    ) -> Result<Self, String> {
+        layout.validate(platform)?;
+        let mut ram = vec![0; layout.memory_size];
+
+        let font = layout.font_address as usize;
+        ram[font..font + FONT_SIZE as usize].copy_from_slice(Font::Octo.bytes());
+        // Only SUPER-CHIP and XO-CHIP have FX30, so plain CHIP-8 layouts
+        // may put the program right after the small font.
+        if platform != Platform::Chip8 {
+            let big_font = font + FONT_SIZE as usize;
+            ram[big_font..big_font + font::BIG_FONT.len()].copy_from_slice(&font::BIG_FONT);
+        }
+
+        Ok(CHIP8 {
+            pc: layout.program_start as usize,
             ram,
+            layout,
             stack: Vec::new(),
+            stack_config: StackConfig::default(),
             delay_timer: 0,
-            sound_timer: 5,
+            sound_timer: layout.initial_sound_timer,
             i_reg: 0,
             vx_reg: [0; 16],
-            old_behaviour_conf,
+            planes: [[[false; HIRES_WIDTH]; HIRES_HEIGHT]; PLANES],
+            selected_planes: 0b01,
+            hires: false,
+            rpl_flags: [0; 16],
+            audio_pattern: Buzzer::default().pattern,
+            pitch: Buzzer::default().pitch,
+            rng: Random::seeded(0),
+            rom_hash: state::rom_hash(&[]),
+            platform,
+            quirks,
+            vblank: true,
+            cycle_overrun: 0,
+            breakpoints: Breakpoints::default(),
+            resume_from: None,
+            watch_hit: None,
+        })
+    }
+
+    /// Replaces the random source used by CXNN. A freshly created machine
+    /// uses a fixed seed, so runs are reproducible unless this is called.
+    /// Save states cannot capture a source set here, so loading one keeps
+    /// it as it is; `seed_rng` has no such limit.
+    pub fn set_rng(&mut self, rng: impl RngCore + Send + 'static) {
+        self.rng = Random::Custom(Box::new(rng));
+    }
+
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Random::seeded(seed);
+    }
+
+    /// Replaces the 4x5 hex font FX29 points into.
+    pub fn set_font(&mut self, font: &[u8; font::FONT_SIZE]) {
+        let start = self.layout.font_address as usize;
+        self.ram[start..start + font.len()].copy_from_slice(font);
+    }
+
+    /// Panics if `stack_config` does not pass `StackConfig::validate`.
+    pub fn set_stack_config(&mut self, stack_config: StackConfig) -> Result<(), String> {
+        stack_config.validate(self.platform, self.layout)?;
+        self.stack_config = stack_config;
+        Ok(())
+    }
+
+    /// Bytes available to a program between the start address and the end
+    /// of memory, or the stack if it is kept in memory above the program.
+    pub fn program_capacity(&self) -> usize {
+        let start = self.layout.program_start as usize;
+        let end = match self.stack_config.ram_start() {
+            Some(stack) if stack > start => stack,
+            _ => self.layout.memory_size,
+        };
+        end - start
+    }
+
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), LoadError> {
+        let size = program.len();
+        let capacity = self.program_capacity();
+        if size == 0 {
+            return Err(LoadError::Empty);
+        }
+        if size > capacity {
+            return Err(LoadError::TooLarge { size, capacity });
+        }
+        if !size.is_multiple_of(2) {
+            return Err(LoadError::OddLength { size });
         }
+        let start = self.layout.program_start as usize;
+        self.ram[start..start + size].copy_from_slice(program);
+        self.rom_hash = state::rom_hash(program);
+        Ok(())
     }
 
-    pub fn load_program(&mut self, program: &[u8]) {
-        self.ram[0x200..(program.len() + 0x200)].copy_from_slice(program);
+    /// The first bitplane, which holds the whole picture on every platform
+    /// but XO-CHIP.
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.planes[0]
     }
 
-    pub fn update(&mut self, input: CHIP8Input, display: &mut Display) -> CHIP8Output {
-        let instruction =
-            u16::from_be_bytes((&self.ram[self.pc..=self.pc + 1]).try_into().unwrap());
-        self.pc += 2;
-        let first_nibble = ((instruction & 0b1111000000000000) >> 12) as u8;
-        let second_nibble = ((instruction & 0b0000111100000000) >> 8) as usize;
-        let third_nibble = ((instruction & 0b0000000011110000) >> 4) as usize;
-        let fourth_nibble = (instruction & 0b0000000000001111) as u8;
+    pub fn plane(&self, plane: usize) -> &Framebuffer {
+        &self.planes[plane]
+    }
 
-        let nn = (instruction & 0x00FF) as u8;
-        let nnn = instruction & 0x0FFF;
+    /// Palette index of a pixel: bit 0 is set by the first plane, bit 1 by
+    /// the second.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.planes[0][y][x] as u8 | (self.planes[1][y][x] as u8) << 1
+    }
+
+    /// The logical resolution currently in use, as (width, height).
+    pub fn display_size(&self) -> (usize, usize) {
+        if self.hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+        }
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn memory_layout(&self) -> MemoryLayout {
+        self.layout
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
 
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Lets the next `update` execute the instruction at `pc` even if a
+    /// breakpoint is set there, as continuing from a breakpoint does.
+    pub fn resume(&mut self) {
+        self.resume_from = Some(self.pc);
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn i_reg(&self) -> u16 {
+        self.i_reg
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.vx_reg
+    }
+
+    /// Return addresses, innermost last.
+    pub fn stack(&self) -> Vec<u16> {
+        (0..self.stack.len())
+            .map(|depth| self.stack_entry(depth))
+            .collect()
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// What the buzzer plays right now.
+    pub fn buzzer(&self) -> Buzzer {
+        Buzzer {
+            pattern: self.audio_pattern,
+            pitch: self.pitch,
+            on: self.sound_timer > 0,
+        }
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.vblank = true;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    pub fn run_cycles(&mut self, n: usize, input: &CHIP8Input) -> Result<CHIP8Output, ExecError> {
+        let mut out = CHIP8Output::default();
+        for _ in 0..n {
+            let res = self.update(input.clone())?;
+            out.request_redraw |= res.request_redraw;
+            out.exit_requested |= res.exit_requested;
+            out.cycles += res.cycles;
+            out.status = res.status;
+            out.log.extend(res.log);
+            if out.status != Status::Running {
+                break;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Runs as many instructions as fit in a COSMAC VIP frame of
+    /// `VIP_CYCLES_PER_FRAME` machine cycles, then ticks the timers. An
+    /// instruction that runs past the end of the budget is paid for by the
+    /// next frame. A breakpoint ends the frame early without ticking them.
+    pub fn run_vip_frame(&mut self, input: &CHIP8Input) -> Result<CHIP8Output, ExecError> {
         let mut out = CHIP8Output {
-            request_redraw: false,
+            cycles: self.cycle_overrun,
+            ..CHIP8Output::default()
         };
-        match first_nibble {
-            0x0 => match nn {
-                0xE0 => {
-                    display.clear_screen();
-                    out.request_redraw = true;
-                }
-                0xEE => {
-                    let address = self.stack.pop().expect("Popped with empty stack.");
-                    self.pc = address as usize;
-                }
-                _ => panic!("unknown opcode"),
-            },
-            0x1 => {
-                self.pc = nnn as usize;
+        while out.cycles < VIP_CYCLES_PER_FRAME && !out.exit_requested {
+            let res = self.update(input.clone())?;
+            out.request_redraw |= res.request_redraw;
+            out.exit_requested |= res.exit_requested;
+            out.cycles += res.cycles;
+            out.status = res.status;
+            out.log.extend(res.log);
+            if out.status != Status::Running {
+                return Ok(out);
+            }
+        }
+        self.cycle_overrun = out.cycles.saturating_sub(VIP_CYCLES_PER_FRAME);
+        self.tick_timers();
+        Ok(out)
+    }
+
+    /// Runs one 60Hz frame: `instructions_per_frame` instructions followed
+    /// by a single timer tick. Frontends decide how often to call this, so
+    /// the same inputs always give the same result. A breakpoint ends the
+    /// frame early without the tick.
+    pub fn step_frame(
+        &mut self,
+        input: &CHIP8Input,
+        instructions_per_frame: usize,
+    ) -> Result<CHIP8Output, ExecError> {
+        let out = self.run_cycles(instructions_per_frame, input)?;
+        if out.status == Status::Running {
+            self.tick_timers();
+        }
+        Ok(out)
+    }
+
+    /// `step_frame` at the default speed of `DEFAULT_INSTRUCTIONS_PER_FRAME`.
+    pub fn run_frame(&mut self, input: CHIP8Input) -> Result<CHIP8Output, ExecError> {
+        self.step_frame(&input, DEFAULT_INSTRUCTIONS_PER_FRAME)
+    }
+
+    /// Runs until `predicate` holds, ticking the timers every
+    /// `DEFAULT_INSTRUCTIONS_PER_FRAME` instructions. Returns the number of
+    /// instructions executed, or `None` if `max_cycles` ran out or a
+    /// breakpoint stopped execution first.
+    pub fn run_until(
+        &mut self,
+        input: &CHIP8Input,
+        max_cycles: usize,
+        mut predicate: impl FnMut(&CHIP8) -> bool,
+    ) -> Result<Option<usize>, ExecError> {
+        for cycle in 0..max_cycles {
+            if predicate(self) {
+                return Ok(Some(cycle));
             }
-            0x2 => {
-                self.stack.push(self.pc as u16);
-                self.pc = nnn as usize;
+            if self.update(input.clone())?.status != Status::Running {
+                return Ok(None);
             }
-            0x3 => {
-                if self.vx_reg[second_nibble] == nn {
-                    self.pc += 2;
-                }
+            if (cycle + 1) % DEFAULT_INSTRUCTIONS_PER_FRAME == 0 {
+                self.tick_timers();
             }
-            0x4 => {
-                if self.vx_reg[second_nibble] != nn {
-                    self.pc += 2;
-                }
+        }
+        Ok(predicate(self).then_some(max_cycles))
+    }
+
+    pub fn update(&mut self, input: CHIP8Input) -> Result<CHIP8Output, ExecError> {
+        if self.pc + 1 >= self.ram.len() {
+            return Err(ExecError::PcOutOfRange { pc: self.pc });
+        }
+        let address = self.pc as u16;
+        let opcode = u16::from_be_bytes([self.ram[self.pc], self.ram[self.pc + 1]]);
+        let mut log = Vec::new();
+        if self.resume_from.take() != Some(self.pc) {
+            let mut out = CHIP8Output::default();
+            if !self.breakpoints.conditions.is_empty() {
+                let hits = self.breakpoints.hit(self.pc);
+                self.check_conditions(address, hits, &mut out);
             }
-            0x5 => match fourth_nibble {
-                0x0 => {
-                    if self.vx_reg[second_nibble] == self.vx_reg[third_nibble] {
-                        self.pc += 2;
-                    }
+            if out.status == Status::Running {
+                if let Some(reason) = self.breakpoints.check_instruction(address, opcode) {
+                    out.status = Status::Stopped(reason);
                 }
-                _ => panic!("unknown opcode"),
-            },
-            0x6 => {
-                self.vx_reg[second_nibble] = nn;
             }
-            0x7 => {
-                self.vx_reg[second_nibble] = self.vx_reg[second_nibble].wrapping_add(nn);
+            if out.status != Status::Running {
+                self.resume_from = Some(self.pc);
+                return Ok(out);
             }
-            0x8 => match fourth_nibble {
-                0x0 => self.vx_reg[second_nibble] = self.vx_reg[third_nibble],
-                0x1 => self.vx_reg[second_nibble] |= self.vx_reg[third_nibble],
-                0x2 => self.vx_reg[second_nibble] &= self.vx_reg[third_nibble],
-                0x3 => self.vx_reg[second_nibble] ^= self.vx_reg[third_nibble],
-                0x4 => {
-                    let overflowed;
-                    (self.vx_reg[second_nibble], overflowed) =
-                        self.vx_reg[second_nibble].overflowing_add(self.vx_reg[third_nibble]);
-                    self.vx_reg[0xf] = if overflowed { 1 } else { 0 };
-                }
-                0x5 => {
-                    let (f, s) = (self.vx_reg[second_nibble], self.vx_reg[third_nibble]);
-                    self.vx_reg[second_nibble] = f.wrapping_sub(s);
-                    self.vx_reg[0xf] = if f > s { 1 } else { 0 };
-                }
-                0x6 => {
-                    if self.old_behaviour_conf.i_8xy6 {
-                        self.vx_reg[second_nibble] = self.vx_reg[third_nibble];
-                    }
-                    let bit = self.vx_reg[second_nibble] & !(0x1);
-                    self.vx_reg[second_nibble] >>= 1;
-                    self.vx_reg[0xF] = bit;
-                }
-                0x7 => {
-                    let (f, s) = (self.vx_reg[third_nibble], self.vx_reg[second_nibble]);
-                    self.vx_reg[second_nibble] = f.wrapping_sub(s);
-                    self.vx_reg[0xf] = if f > s { 1 } else { 0 };
-                }
-                0xE => {
-                    if self.old_behaviour_conf.i_8xye {
-                        self.vx_reg[second_nibble] = self.vx_reg[third_nibble];
-                    }
-                    let bit = self.vx_reg[second_nibble] & 0b1000;
-                    self.vx_reg[second_nibble] <<= 1;
-                    self.vx_reg[0xF] = bit;
-                }
-                _ => panic!("unknown opcode"),
-            },
-            0x9 => match fourth_nibble {
-                0x0 => {
-                    if self.vx_reg[second_nibble] != self.vx_reg[third_nibble] {
-                        self.pc += 2;
-                    }
-                }
-                _ => panic!("unknown opcode"),
-            },
+            log = out.log;
+        }
+        let instruction = instruction::decode_at(&self.ram, self.pc)
+            .ok()
+            .filter(|instruction| instruction.is_supported(self.platform))
+            .ok_or(ExecError::UnknownOpcode { address, opcode })?;
+        let cycles = instruction.vip_cycles(self);
+        self.pc += instruction.len();
+        let next = self.pc;
+        let registers = (self.i_reg, self.vx_reg);
+        self.watch_hit = None;
+        let mut out = instruction.execute(self, &input)?;
+        out.cycles = cycles;
+        out.log.splice(0..0, log);
+        let watch_hit = self.watch_hit.take().or_else(|| {
+            self.breakpoints
+                .check_registers(address, registers, (self.i_reg, self.vx_reg))
+        });
+        if let Some(reason) = watch_hit {
+            out.status = Status::Stopped(reason);
+        }
+        if instruction.is_skip() && self.pc != next {
+            out.cycles += VIP_SKIP_CYCLES;
+        }
+        Ok(out)
+    }
 
-            0xA => {
-                self.i_reg = nnn;
-            }
-            0xB => {
-                self.pc = (nnn
-                    + if self.old_behaviour_conf.bnnn {
-                        self.vx_reg[0]
-                    } else {
-                        self.vx_reg[second_nibble]
-                    } as u16) as usize;
+    /// Skips the next instruction, which on XO-CHIP may be the 4-byte
+    /// `F000 NNNN`.
+    fn skip(&mut self) {
+        let long = self.platform == Platform::XoChip
+            && self.ram.get(self.pc..self.pc + 2) == Some(&[0xF0, 0x00]);
+        self.pc += if long { 4 } else { 2 };
+    }
+
+    /// Memory location of the stack entry at `depth` when it is kept in RAM.
+    fn stack_slot(depth: usize) -> usize {
+        VIP_STACK_ADDRESS as usize - 2 * (depth + 1)
+    }
+
+    fn stack_entry(&self, depth: usize) -> u16 {
+        let slot = Self::stack_slot(depth);
+        match self.ram.get(slot..slot + 2) {
+            Some(bytes) if self.stack_config.in_ram => u16::from_be_bytes([bytes[0], bytes[1]]),
+            _ => self.stack[depth],
+        }
+    }
+
+    fn push_stack(&mut self, address: u16, value: u16) -> Result<(), ExecError> {
+        let depth = self.stack.len();
+        if depth >= self.stack_config.limit {
+            return Err(ExecError::StackOverflow {
+                address,
+                limit: self.stack_config.limit,
+            });
+        }
+        if self.stack_config.in_ram {
+            let [high, low] = value.to_be_bytes();
+            let slot = Self::stack_slot(depth);
+            self.write_ram(address, slot, high)?;
+            self.write_ram(address, slot + 1, low)?;
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn pop_stack(&mut self, address: u16) -> Result<u16, ExecError> {
+        let depth = self
+            .stack
+            .len()
+            .checked_sub(1)
+            .ok_or(ExecError::StackUnderflow { address })?;
+        let value = self.stack_entry(depth);
+        self.stack.pop();
+        Ok(value)
+    }
+
+    fn selected_planes_mut(&mut self) -> impl Iterator<Item = &mut Framebuffer> {
+        let selected = self.selected_planes;
+        self.planes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(plane, _)| selected & (1 << plane) != 0)
+            .map(|(_, plane)| plane)
+    }
+
+    fn read_ram(&mut self, address: u16, index: usize) -> Result<u8, ExecError> {
+        self.watch_memory(address, index, false);
+        self.ram
+            .get(index)
+            .copied()
+            .ok_or(ExecError::MemoryOutOfBounds { address, index })
+    }
+
+    fn write_ram(&mut self, address: u16, index: usize, value: u8) -> Result<(), ExecError> {
+        self.watch_memory(address, index, true);
+        match self.ram.get_mut(index) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
             }
-            0xC => {
-                use rand::prelude::*;
-                let random: u8 = rand::thread_rng().gen();
-                self.vx_reg[second_nibble] = random & nn;
+            None => Err(ExecError::MemoryOutOfBounds { address, index }),
+        }
+    }
+
+    /// Evaluates the conditions and log points against the state before the
+    /// instruction at `address` runs, which execution has now reached `hits`
+    /// times.
+    fn check_conditions(&mut self, address: u16, hits: u32, out: &mut CHIP8Output) {
+        for condition in &self.breakpoints.conditions {
+            if condition.expr.eval(self, hits).unwrap_or(0) == 0 {
+                continue;
             }
-            0xD => {
-                out.request_redraw = true;
-                let mut x;
-                let mut y = self.vx_reg[third_nibble] % 32;
-                self.vx_reg[15] = 0;
-
-                for i in 0..fourth_nibble as usize {
-                    x = self.vx_reg[second_nibble] % 64;
-                    let byte = self.ram[self.i_reg as usize + i];
-                    const BYTE_COMBOS: [(u8, u8); 8] = [
-                        (0b10000000, 7),
-                        (0b01000000, 6),
-                        (0b00100000, 5),
-                        (0b00010000, 4),
-                        (0b00001000, 3),
-                        (0b00000100, 2),
-                        (0b00000010, 1),
-                        (0b00000001, 0),
-                    ];
-                    for combo in BYTE_COMBOS {
-                        let bit = (byte & combo.0) >> combo.1;
-                        if bit != 0 {
-                            if display.get_pixel(x, y) {
-                                display.unset_pixel(x, y);
-                                self.vx_reg[15] = 1;
-                            } else {
-                                display.set_pixel(x, y);
-                            }
-                        }
-                        x += 1;
-                        if x > 63 {
-                            break;
-                        }
-                    }
-
-                    y += 1;
-                    if y > 31 {
-                        break;
-                    }
+            match &condition.log {
+                Some(template) => out.log.push(template.format(self, hits)),
+                None if out.status == Status::Running => {
+                    out.status = Status::Stopped(StopReason::Condition {
+                        pc: address,
+                        source: condition.source.clone(),
+                    });
                 }
+                None => {}
             }
-            0xE => match nn {
-                0x9E => {
-                    if input.pressed_keys[self.vx_reg[second_nibble] as usize] {
-                        self.pc += 2;
-                    }
-                }
-                0xA1 => {
-                    if !input.pressed_keys[self.vx_reg[second_nibble] as usize] {
-                        self.pc += 2;
-                    }
-                }
-                _ => panic!("unknown opcode"),
-            },
-            0xF => match nn {
-                0x1E => {
-                    // make overflow behaviour here configurable
-                    let overflowing;
-                    (self.i_reg, overflowing) = self
-                        .i_reg
-                        .overflowing_add(self.vx_reg[second_nibble] as u16);
-                    if !self.old_behaviour_conf.fx1e {
-                        self.vx_reg[0xF] = if overflowing { 1 } else { 0 }
-                    }
-                }
-                0x0A => {
-                    if input.released_key.is_none() {
-                        self.pc -= 2;
-                    } else {
-                        self.vx_reg[second_nibble] = input.released_key.unwrap() as u8;
-                    }
-                }
-                0x29 => {
-                    self.i_reg = 0x50 + ((self.vx_reg[second_nibble] & 0x0F) as u16) * 5;
-                }
-                0x33 => {
-                    let num = self.vx_reg[second_nibble];
-                    self.ram[self.i_reg as usize] = num / 100;
-                    self.ram[self.i_reg as usize + 1] = (num / 10) % 10;
-                    self.ram[self.i_reg as usize + 2] = num % 10;
-                }
-                0x55 => {
-                    for idx in 0..=second_nibble {
-                        self.ram[self.i_reg as usize
-                            + if !self.old_behaviour_conf.fx55 {
-                                idx
-                            } else {
-                                0
-                            }] = self.vx_reg[idx];
-                        if self.old_behaviour_conf.fx55 {
-                            self.i_reg += 1;
-                        }
-                    }
-                }
-                0x65 => {
-                    for idx in 0..=second_nibble {
-                        self.vx_reg[idx] = self.ram[self.i_reg as usize
-                            + if !self.old_behaviour_conf.fx65 {
-                                idx
-                            } else {
-                                0
-                            }];
-                        if self.old_behaviour_conf.fx65 {
-                            self.i_reg += 1;
-                        }
-                    }
-                }
-                0x07 => {
-                    self.vx_reg[second_nibble] = self.delay_timer;
-                }
-                0x15 => {
-                    self.delay_timer = self.vx_reg[second_nibble];
-                }
-                0x18 => {
-                    self.sound_timer = self.vx_reg[second_nibble];
-                }
-                _ => panic!("unknown opcode"),
-            },
-            _ => {}
         }
-        out
+    }
+
+    /// Records the access if it is the first of the instruction to hit a
+    /// memory watchpoint.
+    fn watch_memory(&mut self, address: u16, index: usize, write: bool) {
+        if self.watch_hit.is_none() && !self.breakpoints.watchpoints.is_empty() {
+            self.watch_hit = self.breakpoints.check_memory(address, index, write);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_buzzer_is_a_500_hz_square_wave() {
+        let buzzer = Buzzer {
+            on: true,
+            ..Buzzer::default()
+        };
+        let mut out = [0.0; 16];
+        let mut phase = 0.0;
+        buzzer.render(&mut out, 4000, &mut phase);
+        let high: Vec<bool> = out.iter().map(|&sample| sample > 0.0).collect();
+        assert_eq!(
+            high[..8],
+            [true, true, true, true, false, false, false, false]
+        );
+        assert_eq!(high[..8], high[8..]);
+    }
+
+    #[test]
+    fn buzzer_is_silent_once_the_sound_timer_runs_out() {
+        let mut chip8 = CHIP8::new(Platform::XoChip, Quirks::default());
+        assert!(chip8.buzzer().on);
+        for _ in 0..5 {
+            chip8.tick_timers();
+        }
+        let mut out = [1.0; 8];
+        chip8.buzzer().render(&mut out, 44100, &mut 0.0);
+        assert_eq!(out, [0.0; 8]);
+    }
+
+    #[test]
+    fn conditions_see_pc_and_hits_of_the_instruction_about_to_run() {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        // 6001: v0 := 1, 7001: v0 += 1, 1202: jump back to the add.
+        chip8
+            .load_program(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02])
+            .unwrap();
+        chip8.breakpoints_mut().conditions =
+            vec![debug::Condition::new("PC == 0x202 && hits == 3", None).unwrap()];
+        let mut statuses = Vec::new();
+        for _ in 0..6 {
+            statuses.push(chip8.update(CHIP8Input::default()).unwrap().status);
+        }
+        assert!(statuses[..5]
+            .iter()
+            .all(|status| *status == Status::Running));
+        assert!(matches!(
+            statuses[5],
+            Status::Stopped(StopReason::Condition { pc: 0x202, .. })
+        ));
+        // Stopped before the third add, which runs on resuming.
+        assert_eq!((chip8.pc, chip8.vx_reg[0]), (0x202, 3));
+        let out = chip8.update(CHIP8Input::default()).unwrap();
+        assert_eq!((out.status, chip8.vx_reg[0]), (Status::Running, 4));
+    }
+
+    #[test]
+    fn log_points_report_before_a_breakpoint_stops() {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        // 6001: v0 := 1, 1202: jump to itself.
+        chip8.load_program(&[0x60, 0x01, 0x12, 0x02]).unwrap();
+        let breakpoints = chip8.breakpoints_mut();
+        breakpoints.addresses.insert(0x202);
+        breakpoints.conditions = vec![debug::Condition::new("1", Some("at {PC:x}")).unwrap()];
+        let logs: Vec<Vec<String>> = (0..4)
+            .map(|_| chip8.update(CHIP8Input::default()).unwrap().log)
+            .collect();
+        // The stop at 0x202 logs once, and resuming from it does not again.
+        assert_eq!(
+            logs,
+            [vec!["at 0x200"], vec!["at 0x202"], vec![], vec!["at 0x202"]]
+        );
+    }
+
+    #[test]
+    fn fonts_must_end_before_the_program_starts() {
+        let layout = |font_address| MemoryLayout {
+            font_address,
+            ..MemoryLayout::default()
+        };
+        // The VIP font ends right where the program starts.
+        assert!(layout(0x1B0).validate(Platform::Chip8).is_ok());
+        assert!(layout(0x1C0).validate(Platform::Chip8).is_err());
+        // The SUPER-CHIP big font follows the small one.
+        assert!(layout(0x1B0).validate(Platform::SuperChip).is_err());
+        assert!(layout(0x110).validate(Platform::SuperChip).is_ok());
+        // A font after the program is the program's business.
+        assert!(layout(0x800).validate(Platform::SuperChip).is_ok());
+        assert!(CHIP8::with_layout(Platform::Chip8, Quirks::default(), layout(0x1C0)).is_err());
+    }
+
+    #[test]
+    fn stack_in_memory_must_fit_below_the_vip_stack_address() {
+        let layout = MemoryLayout::default();
+        let stack = |limit| StackConfig {
+            limit,
+            in_ram: true,
+        };
+        assert!(stack(16).validate(Platform::Chip8, layout).is_ok());
+        // 0xED0 - 2 * 0x740 = 0x50, the start of the font.
+        assert!(stack(0x740).validate(Platform::Chip8, layout).is_err());
+        // 0xED0 - 2 * 0x700 = 0xD0, past the font but below the program.
+        assert!(stack(0x700).validate(Platform::Chip8, layout).is_err());
+        assert!(stack(0x800).validate(Platform::Chip8, layout).is_err());
+        let host = StackConfig {
+            limit: 0x800,
+            in_ram: false,
+        };
+        assert!(host.validate(Platform::Chip8, layout).is_ok());
+    }
+
+    #[test]
+    fn stack_in_memory_limits_the_program_size() {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8
+            .set_stack_config(StackConfig {
+                limit: 12,
+                in_ram: true,
+            })
+            .unwrap();
+        assert_eq!(chip8.program_capacity(), 0xEB8 - 0x200);
+        let too_deep = StackConfig {
+            limit: 0x800,
+            in_ram: true,
+        };
+        assert!(chip8.set_stack_config(too_deep).is_err());
+        assert_eq!(chip8.program_capacity(), 0xEB8 - 0x200);
+    }
+
+    #[test]
+    fn profiles_differ_in_the_quirks_of_their_interpreters() {
+        let chip8 = Profile::Chip8.quirks();
+        assert_eq!(chip8, Quirks::default());
+
+        let vip = Profile::CosmacVip.quirks();
+        assert!(vip.vf_reset && vip.display_wait && vip.fx55 && vip.i_8xy6 && vip.bnnn);
+        assert!(!vip.wrap && !vip.vf_rows);
+
+        let chip48 = Profile::Chip48.quirks();
+        assert_eq!(
+            chip48,
+            Quirks {
+                fx1e: true,
+                ..chip8
+            }
+        );
+
+        let legacy = Profile::SuperChipLegacy.quirks();
+        assert!(legacy.display_wait && legacy.vf_rows && legacy.fx1e);
+        assert!(!legacy.fx55 && !legacy.i_8xy6 && !legacy.bnnn && !legacy.vf_reset);
+
+        let modern = Profile::SuperChipModern.quirks();
+        assert_eq!(
+            modern,
+            Quirks {
+                display_wait: false,
+                vf_rows: false,
+                ..legacy
+            }
+        );
+
+        let xo = Profile::XoChip.quirks();
+        assert!(xo.fx55 && xo.fx65 && xo.i_8xy6 && xo.i_8xye && xo.bnnn && xo.wrap);
+        assert!(!xo.vf_reset && !xo.display_wait && !xo.vf_rows);
+    }
+
+    #[test]
+    fn ibm_logo_runs_headless() {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8
+            .load_program(include_bytes!("../sample/ibmlogo.ch8"))
+            .unwrap();
+        // The ROM ends in a jump to itself at 0x228.
+        let done = chip8.run_until(&CHIP8Input::default(), 1000, |c| c.pc == 0x228);
+        assert!(done.unwrap().is_some());
+        chip8.run_frame(CHIP8Input::default()).unwrap();
+        assert_eq!(chip8.pc, 0x228);
+
+        let logo = [
+            "########.#########...#####.........#####",
+            "",
+            "########.###########.######.......######",
+            "",
+            "..####.....###...###...#####.....#####..",
+            "",
+            "..####.....#######.....#######.#######..",
+            "",
+            "..####.....#######.....###.#######.###..",
+            "",
+            "..####.....###...###...###..#####..###..",
+            "",
+            "########.###########.#####...###...#####",
+            "",
+            "########.#########...#####....#....#####",
+        ];
+        let (width, height) = chip8.display_size();
+        for (y, row) in chip8.framebuffer()[..height].iter().enumerate() {
+            let drawn: String = row[..width]
+                .iter()
+                .map(|&on| if on { '#' } else { '.' })
+                .collect();
+            // The logo is centred in rows 8 to 22.
+            let line = logo.get(y.wrapping_sub(8)).unwrap_or(&"");
+            assert_eq!(drawn, format!("{line:.^64}"), "row {y}");
+        }
+    }
+
+    #[test]
+    fn vip_frames_spend_a_fixed_cycle_budget() {
+        // 7001: v0 += 1 (78 cycles), 1200: jump 0x200 (80 cycles)
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip8.delay_timer = 10;
+
+        // 16 loops take 2528 cycles, and the next add runs 6 past the end.
+        let out = chip8.run_vip_frame(&CHIP8Input::default()).unwrap();
+        assert_eq!(out.cycles, 2606);
+        assert_eq!(
+            (chip8.vx_reg[0], chip8.pc, chip8.cycle_overrun),
+            (17, 0x202, 6)
+        );
+        assert_eq!(chip8.delay_timer, 9);
+
+        // The overrun comes out of the next frame's budget.
+        let out = chip8.run_vip_frame(&CHIP8Input::default()).unwrap();
+        assert_eq!(out.cycles, 2614);
+        assert_eq!(
+            (chip8.vx_reg[0], chip8.pc, chip8.cycle_overrun),
+            (33, 0x200, 14)
+        );
+        assert_eq!(chip8.delay_timer, 8);
+    }
+
+    #[test]
+    fn taken_skips_cost_extra_vip_cycles() {
+        // 3000: skip if v0 == 0, 3100: skip if v1 == 0
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8
+            .load_program(&[0x30, 0x00, 0x00, 0x00, 0x31, 0x00])
+            .unwrap();
+        let taken = chip8.update(CHIP8Input::default()).unwrap();
+        assert_eq!(taken.cycles, 78 + VIP_SKIP_CYCLES);
+        chip8.vx_reg[1] = 1;
+        let not_taken = chip8.update(CHIP8Input::default()).unwrap();
+        assert_eq!(not_taken.cycles, 78);
+    }
+
+    #[test]
+    fn calls_and_returns_past_the_stack_are_errors() {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8.set_stack_config(Profile::CosmacVip.stack()).unwrap();
+        // 2200: call 2200
+        chip8.load_program(&[0x22, 0x00]).unwrap();
+        for _ in 0..12 {
+            chip8.update(CHIP8Input::default()).unwrap();
+        }
+        assert_eq!(
+            chip8.update(CHIP8Input::default()).unwrap_err(),
+            ExecError::StackOverflow {
+                address: 0x200,
+                limit: 12
+            }
+        );
+
+        // 00EE: return
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8.load_program(&[0x00, 0xEE]).unwrap();
+        assert_eq!(
+            chip8.update(CHIP8Input::default()).unwrap_err(),
+            ExecError::StackUnderflow { address: 0x200 }
+        );
+    }
+
+    #[test]
+    fn stack_in_memory_is_read_back_from_memory() {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8.set_stack_config(Profile::CosmacVip.stack()).unwrap();
+        // 2204: call 0x204, 1202: jump 0x202, 2208: call 0x208, 00EE: return
+        let program = [0x22, 0x04, 0x12, 0x02, 0x22, 0x08, 0x00, 0xEE, 0x00, 0xEE];
+        chip8.load_program(&program).unwrap();
+        chip8.update(CHIP8Input::default()).unwrap();
+        chip8.update(CHIP8Input::default()).unwrap();
+        assert_eq!(chip8.stack(), [0x202, 0x206]);
+        // Entries grow down from VIP_STACK_ADDRESS.
+        assert_eq!(&chip8.ram[0xECC..0xED0], [0x02, 0x06, 0x02, 0x02]);
+
+        // The stack survives a save state.
+        let mut restored = CHIP8::new(Platform::Chip8, Quirks::default());
+        restored.load_program(&program).unwrap();
+        restored.load_state(&chip8.save_state()).unwrap();
+        assert_eq!(restored.stack(), [0x202, 0x206]);
+
+        // A program overwriting its return address returns there.
+        restored.ram[0xECC..0xECE].copy_from_slice(&[0x02, 0x02]);
+        restored.update(CHIP8Input::default()).unwrap();
+        assert_eq!((restored.pc, restored.stack()), (0x202, vec![0x202]));
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_frames() {
+        // C03F, C11F: v0, v1 := random, A20A: i := 0x20A, D011: sprite,
+        // 1200: jump 0x200, then a one-pixel sprite.
+        let program = [
+            0xC0, 0x3F, 0xC1, 0x1F, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x00, 0x80, 0x00,
+        ];
+        let run = |seed| {
+            let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+            chip8.load_program(&program).unwrap();
+            chip8.seed_rng(seed);
+            let mut frames = Vec::new();
+            for _ in 0..20 {
+                chip8.run_frame(CHIP8Input::default()).unwrap();
+                frames.push((chip8.vx_reg, *chip8.framebuffer()));
+            }
+            frames
+        };
+        assert!(run(42) == run(42));
+        assert!(run(42) != run(43));
     }
 }
@@ -2,33 +2,254 @@ use chip8::*;
 
 use chip8::app::ColorConfig;
 
+struct Options {
+    rom: Vec<u8>,
+    rom_path: String,
+    platform: Platform,
+    quirks: Quirks,
+    stack_config: StackConfig,
+    layout: MemoryLayout,
+    font: [u8; font::FONT_SIZE],
+    timing: app::Timing,
+    color_conf: ColorConfig,
+    seed: Option<u64>,
+    rewind_depth: usize,
+    breakpoints: Breakpoints,
+    gdb_port: Option<u16>,
+}
+
 fn main() {
-    let (rom, old_behaviour_conf, tick_time, color_conf) = handle_args();
-    app::drive(&rom, old_behaviour_conf, tick_time, color_conf).unwrap();
+    let debug = match std::env::args().nth(1).as_deref() {
+        Some("disasm") => return disasm_command(),
+        Some("asm") => return asm_command(),
+        Some("debug") => true,
+        _ => false,
+    };
+
+    let options = handle_args(debug);
+    let mut chip8 = match CHIP8::with_layout(options.platform, options.quirks, options.layout) {
+        Ok(chip8) => chip8,
+        Err(err) => {
+            eprintln!("Invalid memory layout: {err}.");
+            std::process::exit(1);
+        }
+    };
+    // Always seeded so that save states can restore the random stream.
+    chip8.seed_rng(options.seed.unwrap_or_else(rand::random));
+    chip8.set_font(&options.font);
+    if let Err(err) = chip8.set_stack_config(options.stack_config) {
+        eprintln!("Invalid stack: {err}.");
+        std::process::exit(1);
+    }
+    *chip8.breakpoints_mut() = options.breakpoints;
+    let mut loaded = chip8.load_program(&options.rom);
+    if let Err(err @ LoadError::OddLength { .. }) = &loaded {
+        // Plenty of real ROMs end in a lone data byte, so this only warns.
+        eprintln!(
+            "Warning: \"{}\": {}, padding it with a zero byte.",
+            options.rom_path, err
+        );
+        let mut padded = options.rom.clone();
+        padded.push(0);
+        loaded = chip8.load_program(&padded);
+    }
+    if let Err(err) = loaded {
+        eprintln!("Could not load \"{}\": {}.", options.rom_path, err);
+        if let LoadError::TooLarge { size, .. } = err {
+            suggest_platform(options.layout, size);
+        }
+        std::process::exit(1)
+    }
+    // Save states are keyed to the ROM path, which stdin does not have.
+    let rom_path = (options.rom_path != "-").then(|| std::path::Path::new(&options.rom_path));
+    let debugger: Option<Box<dyn app::Debugger>> = match (debug, options.gdb_port) {
+        (true, Some(_)) => {
+            eprintln!("--gdb cannot be combined with chip8 debug.");
+            std::process::exit(1)
+        }
+        (true, None) => match tui::TerminalDebugger::new() {
+            Ok(e) => Some(Box::new(e)),
+            Err(err) => {
+                eprintln!("Could not start the debugger: {err}.");
+                std::process::exit(1)
+            }
+        },
+        (false, Some(port)) => match gdb::GdbStub::listen(port) {
+            Ok(e) => {
+                println!("Waiting for gdb on localhost:{port}.");
+                Some(Box::new(e))
+            }
+            Err(err) => {
+                eprintln!("Could not listen on port {port}: {err}.");
+                std::process::exit(1)
+            }
+        },
+        (false, None) => None,
+    };
+    app::drive(
+        chip8,
+        rom_path,
+        options.timing,
+        options.color_conf,
+        options.rewind_depth,
+        debugger,
+    )
+    .unwrap();
     println!("Hello, world!");
 }
 
-fn handle_args() -> (
-    Vec<u8>,
-    OldBehaviourConfig,
-    std::time::Duration,
-    ColorConfig,
-) {
+/// Lists the profiles whose memory and stack leave room for a ROM that
+/// did not fit the selected configuration, keeping its program start.
+fn suggest_platform(layout: MemoryLayout, size: usize) {
+    let profiles = [
+        ("CHIP8", Profile::Chip8),
+        ("VIP", Profile::CosmacVip),
+        ("CHIP48", Profile::Chip48),
+        ("SCHIP-LEGACY", Profile::SuperChipLegacy),
+        ("SCHIP", Profile::SuperChipModern),
+        ("XOCHIP", Profile::XoChip),
+    ];
+    let fits = |profile: Profile| {
+        let layout = MemoryLayout {
+            program_start: layout.program_start,
+            ..profile.memory_layout()
+        };
+        let Ok(mut chip8) = CHIP8::with_layout(profile.platform(), profile.quirks(), layout) else {
+            return false;
+        };
+        chip8.set_stack_config(profile.stack()).is_ok() && size <= chip8.program_capacity()
+    };
+    let names: Vec<&str> = profiles
+        .into_iter()
+        .filter(|&(_, profile)| fits(profile))
+        .map(|(name, _)| name)
+        .collect();
+    if !names.is_empty() {
+        eprintln!("The ROM would fit with --platform {}.", names.join(", "));
+    }
+}
+
+fn disasm_command() {
+    let mut filepath = None;
+    let mut data_style = disasm::DataStyle::Hex;
+    for e in std::env::args().skip(2) {
+        match e.as_str() {
+            "--sprites" => data_style = disasm::DataStyle::Sprites,
+            "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => {
+                filepath.replace(e);
+            }
+        }
+    }
+    let filepath = match filepath {
+        Some(e) => e,
+        None => {
+            eprint!("Path to ROM not provided.\nUSAGE:\n{}", USAGE);
+            std::process::exit(1)
+        }
+    };
+    let rom = match rom::read(&filepath) {
+        Ok(e) => e,
+        Err(err) => {
+            eprintln!("Could not read file \"{}\": {}.", filepath, err);
+            std::process::exit(1)
+        }
+    };
+    print!("{}", disasm::disassemble(&rom, data_style));
+}
+
+fn asm_command() {
+    let mut args = std::env::args().skip(2);
+    let mut filepath = None;
+    let mut output = None;
+    while let Some(e) = args.next() {
+        match e.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(e) => {
+                    output.replace(e);
+                }
+                None => {
+                    eprintln!("Argument not provided for --output.\nUSAGE:\n{}", USAGE);
+                    std::process::exit(1);
+                }
+            },
+            "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => {
+                filepath.replace(e);
+            }
+        }
+    }
+    let filepath = match filepath {
+        Some(e) => e,
+        None => {
+            eprint!("Path to source file not provided.\nUSAGE:\n{}", USAGE);
+            std::process::exit(1)
+        }
+    };
+    let source = match std::fs::read_to_string(&filepath) {
+        Ok(e) => e,
+        Err(_) => {
+            eprintln!("Could not read file \"{}\".", filepath);
+            std::process::exit(1)
+        }
+    };
+    let rom = match asm::assemble(&source) {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("{filepath}:{e}");
+            std::process::exit(1)
+        }
+    };
+    let output = output.unwrap_or_else(|| {
+        std::path::Path::new(&filepath)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+    if std::fs::write(&output, rom).is_err() {
+        eprintln!("Could not write file \"{}\".", output);
+        std::process::exit(1)
+    }
+}
+
+/// `debug` skips the subcommand of `chip8 debug`, which otherwise takes the
+/// same arguments.
+fn handle_args(debug: bool) -> Options {
     let mut args = std::env::args();
     args.next().unwrap();
+    if debug {
+        args.next();
+    }
     let mut filepath = None;
-    let mut oldbeh = OldBehaviourConfig {
-        fx65: false,
-        fx55: false,
-        i_8xy6: false,
-        i_8xye: false,
-        bnnn: false,
-        fx1e: false,
-    };
+    // Overrides are applied on top of the profile once all arguments are
+    // read, so their order relative to --platform does not matter.
+    let mut quirk_overrides: Vec<(String, bool)> = Vec::new();
     let mut bg_color: Option<(u8, u8, u8)> = None;
     let mut fg_on_color: Option<(u8, u8, u8)> = None;
     let mut fg_off_color: Option<(u8, u8, u8)> = None;
-    let mut ticktime: Option<u64> = None;
+    let mut fg_plane2_color: Option<(u8, u8, u8)> = None;
+    let mut fg_both_color: Option<(u8, u8, u8)> = None;
+    let mut ipf: Option<usize> = None;
+    let mut stack_limit: Option<usize> = None;
+    let mut stack_in_ram: Option<bool> = None;
+    let mut program_start: Option<usize> = None;
+    let mut font_address: Option<usize> = None;
+    let mut memory_size: Option<usize> = None;
+    let mut initial_sound_timer: Option<usize> = None;
+    let mut font: Option<[u8; font::FONT_SIZE]> = None;
+    let mut vip_timing = false;
+    let mut seed: Option<u64> = None;
+    let mut rewind_seconds: Option<usize> = None;
+    let mut profile: Option<Profile> = None;
+    let mut use_database = true;
+    let mut breakpoints = Breakpoints::default();
+    let mut gdb_port: Option<u16> = None;
     while let Some(e) = args.next() {
         let e: &str = &e;
         match e {
@@ -44,38 +265,294 @@ fn handle_args() -> (
                     }
                 };
 
-                let instruction: &str = &instruction;
-                match instruction {
-                    "fx65" => oldbeh.fx65 = true,
-                    "fx55" => oldbeh.fx55 = true,
-                    "8xye" => oldbeh.i_8xye = true,
-                    "8xy6" => oldbeh.i_8xy6 = true,
-                    "bnnn" => oldbeh.bnnn = true,
-                    "fx1e" => oldbeh.fx1e = true,
-                    e => {
-                        eprintln!("{e} is not a valid instruction name. Valid names are: [FX65|FX55|8XYE|8XY6|BNNN|FX1E].");
+                quirk_overrides.push((instruction, true));
+            }
+            "-q" | "--quirk" => {
+                let setting = match args.next() {
+                    Some(e) => e.to_lowercase(),
+                    None => {
+                        eprintln!("Quirk setting not provided for --quirk.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1);
+                    }
+                };
+
+                let value = match setting.split_once('=') {
+                    Some((name, "on")) => (name.to_owned(), true),
+                    Some((name, "off")) => (name.to_owned(), false),
+                    _ => {
+                        eprintln!("Quirk setting \"{setting}\" is not of the form NAME=on or NAME=off.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1)
+                    }
+                };
+                quirk_overrides.push(value);
+            }
+            "-p" | "--platform" => {
+                let name = match args.next() {
+                    Some(e) => e.to_lowercase(),
+                    None => {
+                        eprintln!("Platform not provided for --platform.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1);
+                    }
+                };
+
+                profile.replace(match Profile::from_name(&name) {
+                    Some(e) => e,
+                    None => {
+                        eprintln!("{name} is not a valid platform. Valid platforms are: [CHIP8|VIP|CHIP48|SCHIP-LEGACY|SCHIP|XOCHIP].");
+                        std::process::exit(1)
+                    }
+                });
+            }
+            "--ipf" => {
+                let e = match args.next() {
+                    Some(e) => e.parse::<usize>(),
+                    None => {
+                        eprintln!("Argument not provided for --ipf.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1);
+                    }
+                };
+
+                ipf.replace(match e {
+                    Ok(e) if e > 0 => e,
+                    _ => {
+                        eprintln!(
+                            "Argument for --ipf is not a positive number.\nUSAGE:\n{}",
+                            USAGE
+                        );
+                        std::process::exit(1);
+                    }
+                });
+            }
+            "--vip-timing" => vip_timing = true,
+            "--no-database" => use_database = false,
+            "--break" => {
+                let address = handle_address(&mut args, "--break");
+                match address.try_into() {
+                    Ok(e) => breakpoints.addresses.insert(e),
+                    Err(_) => {
+                        eprintln!("Argument for --break is too large.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1)
+                    }
+                };
+            }
+            "--break-opcode" => {
+                let pattern = match args.next() {
+                    Some(e) => e,
+                    None => {
+                        eprintln!("Opcode not provided for --break-opcode.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1);
+                    }
+                };
+
+                match debug::OpcodePattern::parse(&pattern) {
+                    Some(e) => breakpoints.opcodes.push(e),
+                    None => {
+                        eprintln!("{pattern} is not a four character opcode pattern.");
+                        std::process::exit(1)
+                    }
+                }
+            }
+            "--break-if" => {
+                let expr = match args.next() {
+                    Some(e) => e,
+                    None => {
+                        eprintln!("Expression not provided for --break-if.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1);
+                    }
+                };
+
+                match debug::Condition::new(&expr, None) {
+                    Ok(e) => breakpoints.conditions.push(e),
+                    Err(err) => {
+                        eprintln!("Invalid expression \"{expr}\": {err}.");
+                        std::process::exit(1)
+                    }
+                }
+            }
+            "--log" => {
+                let (expr, message) = match (args.next(), args.next()) {
+                    (Some(expr), Some(message)) => (expr, message),
+                    _ => {
+                        eprintln!(
+                            "Expression and message not provided for --log.\nUSAGE:\n{}",
+                            USAGE
+                        );
+                        std::process::exit(1);
+                    }
+                };
+
+                match debug::Condition::new(&expr, Some(&message)) {
+                    Ok(e) => breakpoints.conditions.push(e),
+                    Err(err) => {
+                        eprintln!("Invalid log point \"{expr}\" \"{message}\": {err}.");
+                        std::process::exit(1)
+                    }
+                }
+            }
+            "--watch" => {
+                let watch = match args.next() {
+                    Some(e) => e,
+                    None => {
+                        eprintln!("Argument not provided for --watch.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1);
+                    }
+                };
+
+                match debug::Watchpoint::parse(&watch) {
+                    Some(e) => breakpoints.watchpoints.push(e),
+                    None => {
+                        eprintln!("{watch} is not a register or memory range to watch.");
                         std::process::exit(1)
                     }
                 }
             }
-            "-tt" | "--tick-time" => {
+            "--stack-limit" => {
+                let e = match args.next() {
+                    Some(e) => e.parse::<usize>(),
+                    None => {
+                        eprintln!(
+                            "Argument not provided for --stack-limit.\nUSAGE:\n{}",
+                            USAGE
+                        );
+                        std::process::exit(1);
+                    }
+                };
+
+                stack_limit.replace(match e {
+                    Ok(e) => e,
+                    Err(_) => {
+                        eprintln!(
+                            "Argument for --stack-limit is not a number.\nUSAGE:\n{}",
+                            USAGE
+                        );
+                        std::process::exit(1);
+                    }
+                });
+            }
+            "--stack" => {
+                let name = match args.next() {
+                    Some(e) => e.to_lowercase(),
+                    None => {
+                        eprintln!("Argument not provided for --stack.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1);
+                    }
+                };
+
+                stack_in_ram.replace(match name.as_str() {
+                    "host" => false,
+                    "ram" => true,
+                    e => {
+                        eprintln!(
+                            "{e} is not a valid stack location. Valid locations are: [HOST|RAM]."
+                        );
+                        std::process::exit(1)
+                    }
+                });
+            }
+            "--gdb" => {
+                let e = match args.next() {
+                    Some(e) => e.parse::<u16>(),
+                    None => {
+                        eprintln!("Port not provided for --gdb.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1);
+                    }
+                };
+
+                gdb_port.replace(match e {
+                    Ok(e) => e,
+                    Err(_) => {
+                        eprintln!(
+                            "Argument for --gdb is not a port number.\nUSAGE:\n{}",
+                            USAGE
+                        );
+                        std::process::exit(1);
+                    }
+                });
+            }
+            "--seed" => {
                 let e = match args.next() {
                     Some(e) => e.parse::<u64>(),
                     None => {
-                        eprintln!("Argument not provided for --tick-time.\nUSAGE:\n{}", USAGE);
+                        eprintln!("Argument not provided for --seed.\nUSAGE:\n{}", USAGE);
                         std::process::exit(1);
                     }
                 };
 
-                ticktime.replace(match e {
+                seed.replace(match e {
                     Ok(e) => e,
                     Err(_) => {
+                        eprintln!("Argument for --seed is not a number.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1);
+                    }
+                });
+            }
+            "-rw" | "--rewind-seconds" => {
+                let e = match args.next() {
+                    Some(e) => e.parse::<usize>(),
+                    None => {
                         eprintln!(
-                            "Argument for --tick-time is not a number.\nUSAGE:\n{}",
+                            "Argument not provided for --rewind-seconds.\nUSAGE:\n{}",
                             USAGE
                         );
                         std::process::exit(1);
                     }
+                };
+
+                rewind_seconds.replace(match e {
+                    Ok(e) => e,
+                    Err(_) => {
+                        eprintln!(
+                            "Argument for --rewind-seconds is not a number.\nUSAGE:\n{}",
+                            USAGE
+                        );
+                        std::process::exit(1);
+                    }
+                });
+            }
+            "--program-start" => {
+                program_start.replace(handle_address(&mut args, "--program-start"));
+            }
+            "--font-address" => {
+                font_address.replace(handle_address(&mut args, "--font-address"));
+            }
+            "--memory-size" => {
+                memory_size.replace(handle_address(&mut args, "--memory-size"));
+            }
+            "--initial-sound-timer" => {
+                initial_sound_timer.replace(handle_address(&mut args, "--initial-sound-timer"));
+            }
+            "--font" => {
+                let name = match args.next() {
+                    Some(e) => e,
+                    None => {
+                        eprintln!("Font not provided for --font.\nUSAGE:\n{}", USAGE);
+                        std::process::exit(1);
+                    }
+                };
+
+                font.replace(match Font::from_name(&name) {
+                    Some(e) => *e.bytes(),
+                    None => {
+                        let bytes = match std::fs::read(&name) {
+                            Ok(e) => e,
+                            Err(_) => {
+                                eprintln!("{name} is neither a font name nor a readable file. Valid names are: [OCTO|VIP|DREAM6800|ETI660|FISHNCHIPS].");
+                                std::process::exit(1)
+                            }
+                        };
+                        match bytes.try_into() {
+                            Ok(e) => e,
+                            Err(bytes) => {
+                                let bytes: Vec<u8> = bytes;
+                                eprintln!(
+                                    "Font file \"{name}\" is {} bytes long, but a font is {} bytes.",
+                                    bytes.len(),
+                                    font::FONT_SIZE
+                                );
+                                std::process::exit(1)
+                            }
+                        }
+                    }
                 });
             }
             "-bg" | "--bg-color" => {
@@ -87,6 +564,12 @@ fn handle_args() -> (
             "-fg-on" | "--fg-on-color" => {
                 fg_on_color.replace(handle_color(&mut args, "--fg-on-color"));
             }
+            "-fg-2" | "--fg-plane2-color" => {
+                fg_plane2_color.replace(handle_color(&mut args, "--fg-plane2-color"));
+            }
+            "-fg-3" | "--fg-both-color" => {
+                fg_both_color.replace(handle_color(&mut args, "--fg-both-color"));
+            }
             "--help" => {
                 println!("{}", USAGE);
                 std::process::exit(0);
@@ -96,6 +579,28 @@ fn handle_args() -> (
             }
         }
     }
+    /// Reads a decimal or 0x-prefixed hexadecimal number.
+    fn handle_address(args: &mut std::env::Args, option: &str) -> usize {
+        let string = match args.next() {
+            Some(e) => e.to_lowercase(),
+            None => {
+                eprintln!("Argument not provided for {option}.\nUSAGE:\n{}", USAGE);
+                std::process::exit(1);
+            }
+        };
+
+        let parsed = match string.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => string.parse::<usize>(),
+        };
+        match parsed {
+            Ok(e) => e,
+            Err(_) => {
+                eprintln!("Argument for {option} is not a number.\nUSAGE:\n{}", USAGE);
+                std::process::exit(1);
+            }
+        }
+    }
     fn handle_color(args: &mut std::env::Args, option: &str) -> (u8, u8, u8) {
         let string = match args.next() {
             Some(e) => e,
@@ -105,7 +610,7 @@ fn handle_args() -> (
             }
         };
 
-        if string.len() != 6 || string.as_bytes().len() != 6 {
+        if string.len() != 6 || !string.is_ascii() {
             eprintln!("Invalid color format for {option}.\nUSAGE\n{}", USAGE);
             std::process::exit(1);
         }
@@ -129,32 +634,170 @@ fn handle_args() -> (
             std::process::exit(1)
         }
     };
-    let ticktime = std::time::Duration::from_micros(ticktime.unwrap_or(1430));
-    (
-        match std::fs::read(&filepath) {
-            Ok(e) => e,
-            Err(_) => {
-                eprintln!("Could not read file \"{}\".", filepath);
+    let rom = match rom::read(&filepath) {
+        Ok(e) => e,
+        Err(err) => {
+            eprintln!("Could not read file \"{}\": {}.", filepath, err);
+            std::process::exit(1)
+        }
+    };
+    // Known ROMs fill in whatever the command line left unset.
+    let entry = match use_database {
+        true => database::lookup(&rom).unwrap_or_default(),
+        false => database::Entry::default(),
+    };
+    if let Some(title) = entry.title {
+        println!("{title}");
+    }
+    if let Some(keys) = entry.keys {
+        println!("Keys: {keys}");
+    }
+    let mut quirks = match profile {
+        Some(e) => e.quirks(),
+        None => {
+            let mut quirks = entry.profile.unwrap_or(Profile::Chip8).quirks();
+            entry.apply_quirks(&mut quirks);
+            quirks
+        }
+    };
+    let profile = profile.or(entry.profile).unwrap_or(Profile::Chip8);
+    for (name, value) in quirk_overrides {
+        match quirks.get_mut(&name) {
+            Some(quirk) => *quirk = value,
+            None => {
+                eprintln!("{name} is not a valid quirk name. Valid names are: [FX65|FX55|8XYE|8XY6|BNNN|FX1E|VFRESET|WRAP|VBLANK|VFROWS].");
                 std::process::exit(1)
             }
+        }
+    }
+    let mut stack_config = profile.stack();
+    if let Some(limit) = stack_limit {
+        stack_config.limit = limit;
+    }
+    if let Some(in_ram) = stack_in_ram {
+        stack_config.in_ram = in_ram;
+    }
+    let mut layout = profile.memory_layout();
+    let too_large = |option: &str| -> ! {
+        eprintln!("Argument for {option} is too large.\nUSAGE:\n{}", USAGE);
+        std::process::exit(1)
+    };
+    if let Some(start) = program_start {
+        layout.program_start = start
+            .try_into()
+            .unwrap_or_else(|_| too_large("--program-start"));
+    }
+    if let Some(address) = font_address {
+        layout.font_address = address
+            .try_into()
+            .unwrap_or_else(|_| too_large("--font-address"));
+    }
+    if let Some(size) = memory_size {
+        layout.memory_size = size;
+    }
+    if let Some(value) = initial_sound_timer {
+        layout.initial_sound_timer = value
+            .try_into()
+            .unwrap_or_else(|_| too_large("--initial-sound-timer"));
+    }
+    let timing = if vip_timing {
+        app::Timing::Vip
+    } else {
+        let ipf = ipf.or(entry.instructions_per_frame);
+        app::Timing::InstructionsPerFrame(ipf.unwrap_or(profile.instructions_per_frame()))
+    };
+    let palette = |index: usize| entry.palette.get(index).copied();
+    Options {
+        rom,
+        rom_path: filepath,
+        platform: profile.platform(),
+        quirks,
+        stack_config,
+        layout,
+        font: font.unwrap_or(*profile.font().bytes()),
+        timing,
+        color_conf: ColorConfig {
+            bg_color: bg_color.or(palette(0)).unwrap_or((76, 13, 179)),
+            fg_on_color: fg_on_color.or(palette(1)).unwrap_or((255, 255, 255)),
+            fg_off_color: fg_off_color.or(palette(0)).unwrap_or((0, 0, 0)),
+            fg_plane2_color: fg_plane2_color.or(palette(2)).unwrap_or((255, 102, 0)),
+            fg_both_color: fg_both_color.or(palette(3)).unwrap_or((102, 34, 0)),
         },
-        oldbeh,
-        ticktime,
-        ColorConfig {
-            bg_color: bg_color.unwrap_or((76, 13, 179)),
-            fg_on_color: fg_on_color.unwrap_or((255, 255, 255)),
-            fg_off_color: fg_off_color.unwrap_or((0, 0, 0)),
-        },
-    )
+        seed,
+        rewind_depth: rewind_seconds.unwrap_or(10) * 60,
+        breakpoints,
+        gdb_port,
+    }
 }
 
 const USAGE: &str = r#"
     chip8 [path to rom] [args]
+    chip8 disasm [path to rom] [--sprites]
+    chip8 asm [path to source] [-o path to rom]
+    chip8 debug [path to rom] [args]
+
+The ROM may be a .zip archive holding a single ROM, or - to read it from standard input (save states are then disabled).
+disasm prints the ROM as Octo source that asm turns back into the same ROM. Data is printed as hex bytes, or with --sprites one byte per line with its bitmap.
+asm assembles a subset of Octo (labels, :const, :alias, register ops, if/then/begin/else/end, loop/while/again,
+sprite, i :=, byte literals and the SUPER-CHIP and XO-CHIP statements). The ROM is written next to the source with a .ch8 extension unless -o is given.
+debug runs the ROM paused, with a terminal debugger beside the window showing the disassembly, registers, stack, timers and memory.
+In the terminal, s steps, n steps over calls, r runs to the cursor, c continues, p pauses, b toggles a breakpoint on the cursor line,
+the arrow keys move the cursor, PageUp/PageDown scroll the memory and q quits. : enters a command: break ADDR, break-opcode PATTERN,
+watch SPEC (as for the options below), if EXPR, log EXPR "MESSAGE" (as --break-if and --log), delete [ADDR], goto ADDR or mem ADDR.
 
 Args:
-    -ob,     --old-behaviour [FX65|FX55|8XY6|8XYE|BNNN|FX1E]     Use older behaviour for given instruction
-    -tt,     --tick-time [number in microseconds]                Sets the minimum time a single tick (instruction loop) takes. This does not affect the timers.
+    -p,      --platform [CHIP8|VIP|CHIP48|SCHIP-LEGACY|SCHIP|XOCHIP]
+                                                                 Selects the platform and quirk profile to emulate (CHIP8 by default).
+                                                                 VIP is the original COSMAC VIP interpreter, CHIP48 the HP48 port.
+                                                                 SCHIP-LEGACY and SCHIP add the SUPER-CHIP 1.1 instructions and 128x64 hi-res mode, with the quirks
+                                                                 of the HP48 original and of modern interpreters respectively.
+                                                                 XOCHIP adds 64KiB of memory, two bitplanes, sprite wrapping and the XO-CHIP instructions.
+    -q,      --quirk [NAME=on|NAME=off]                          Overrides a single quirk of the selected profile. Names are:
+                                                                 FX65, FX55   I is incremented past the registers loaded or stored
+                                                                 8XY6, 8XYE   the shift reads VY instead of VX
+                                                                 BNNN         the jump adds V0 instead of VX
+                                                                 FX1E         VF is left alone instead of flagging overflow
+                                                                 VFRESET      8XY1, 8XY2 and 8XY3 reset VF
+                                                                 WRAP         sprites wrap around the screen edges instead of being clipped
+                                                                 VBLANK       DXYN waits for the next frame before drawing in lo-res
+                                                                 VFROWS       hi-res DXYN sets VF to the number of rows that collided or were clipped
+    -ob,     --old-behaviour [NAME]                              Same as --quirk NAME=on.
+             --no-database                                       Ignores the bundled database of known ROMs. Otherwise a ROM found there by its SHA-1 gets its
+                                                                 platform, quirks, speed and palette from it unless set here, and its title and keys are printed.
+             --break [address]                                   Stops before the instruction at the address. Press P to continue.
+             --break-opcode [pattern]                            Stops before any instruction matching the pattern, e.g. 00E0 or DXYN. Characters other than
+                                                                 hex digits match anything.
+             --watch [I|VX|address|start-end][:r|:w]             Stops after an instruction changes the register, or reads or writes the memory. A :r or :w suffix
+                                                                 only watches reads or writes.
+             --break-if [expression]                             Stops before any instruction at which the expression is true, e.g. "V3 == 5 && I > 0x300",
+                                                                 "[I+2] != 0", "DT == 0" or "PC == 0x2A4 && hits > 10". Expressions can use V0-VF, I, PC, DT, ST,
+                                                                 SP (stack depth), hits (how often execution reached PC, counted from the first --break-if,
+                                                                 --log or debugger condition), [address] for a byte of memory, numbers in decimal, 0x hex or 0b
+                                                                 binary, and the operators of C with C's precedence.
+             --log [expression] [message]                        Prints the message whenever the expression holds, without stopping. {expression} in the message is
+                                                                 replaced by its value, or in hex with {expression:x}, e.g. --log "PC == 0x2A4" "score {V3}".
+             --gdb [port]                                        Runs the ROM paused until gdb connects to the port on localhost with "target remote :PORT", then
+                                                                 lets it read and write the registers (V0-VF, I, PC, DT, ST, SP) and memory, set breakpoints,
+                                                                 step and continue. gdb reads the register layout from the target description the stub provides.
+             --ipf [number]                                      Sets how many instructions run every frame (60 frames per second). Defaults to 12 for CHIP8, 15 for VIP,
+                                                                 30 for CHIP48 and the SCHIP profiles and 100 for XOCHIP. Can be changed while running with + and -.
+             --vip-timing                                        Charges every instruction the machine cycles it took on the COSMAC VIP and runs each frame for as many
+                                                                 cycles as the VIP had, instead of using --ipf. Best combined with --platform VIP.
+             --stack-limit [number]                              Sets how many nested calls fit on the stack before the emulation halts (12 for VIP, 16 otherwise).
+             --stack [HOST|RAM]                                  Keeps the call stack outside the machine, or in memory below 0xED0 as the COSMAC VIP did (the VIP default).
+             --program-start [address]                           Where the ROM is loaded and execution starts (0x200 by default, e.g. 0x600 for ETI-660 programs).
+             --font-address [address]                            Where the hex font is stored (0x50 by default, 0x1B0 for VIP). The SUPER-CHIP big font follows it.
+             --memory-size [bytes]                               Size of the machine's memory (0x1000 by default, 0x10000 for XOCHIP).
+             --initial-sound-timer [number]                      Sound timer value at start-up (5 by default).
+                                                                 Addresses and sizes can be given in decimal or as 0x-prefixed hexadecimal.
+             --font [OCTO|VIP|DREAM6800|ETI660|FISHNCHIPS|path to file]
+                                                                 Selects the hex font FX29 draws from (VIP for the VIP profile, OCTO otherwise). A file must hold
+                                                                 exactly 80 bytes: five rows for each digit from 0 to F.
+             --seed [number]                                     Seeds the random number generator used by CXNN, making runs reproducible.
+    -rw,     --rewind-seconds [number]                           How many seconds of play can be rewound with Backspace (10 by default, 0 disables rewinding).
     -bg,     --bg-color [color code]                             Sets the background color of the emulator. Color code format is RRBBGG (e.g. -bg FFFFFF to set it to white).
     -fg-off, --fg-off-color [color code]                         Sets the color of "off" pixels (black by default).
     -fg-on,  --fg-on-color  [color code]                         Sets the color of "on" pixels (white by default).
+    -fg-2,   --fg-plane2-color [color code]                      Sets the color of pixels lit only in the second XO-CHIP plane (FF6600 by default).
+    -fg-3,   --fg-both-color [color code]                        Sets the color of pixels lit in both XO-CHIP planes (662200 by default).
 "#;
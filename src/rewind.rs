@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+/// Ring buffer of machine snapshots for stepping backwards in time.
+///
+/// Only the newest snapshot is kept whole. Every older one is stored as the
+/// zero-run compressed XOR against its successor, so consecutive frames that
+/// differ in a handful of bytes cost a handful of bytes.
+pub struct Rewind {
+    depth: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(depth: usize) -> Self {
+        Rewind {
+            depth,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.depth == 0 {
+            return;
+        }
+        if let Some(previous) = self.latest.replace(state) {
+            let delta = encode(self.latest.as_ref().unwrap(), &previous);
+            self.deltas.push_back(delta);
+            if self.deltas.len() >= self.depth {
+                self.deltas.pop_front();
+            }
+        }
+    }
+
+    /// Removes and returns the newest snapshot.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let current = self.latest.take()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.latest = Some(decode(&current, &delta));
+        }
+        Some(current)
+    }
+}
+
+/// Delta turning `from` into `to`: the length of `to`, then alternating
+/// runs of (zero count, literal count, literal bytes) of `from ^ to`.
+fn encode(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let len = from.len().max(to.len());
+    let xor: Vec<u8> = (0..len)
+        .map(|i| from.get(i).unwrap_or(&0) ^ to.get(i).unwrap_or(&0))
+        .collect();
+
+    let mut out = (to.len() as u32).to_le_bytes().to_vec();
+    let mut i = 0;
+    while i < xor.len() {
+        let zeros = xor[i..]
+            .iter()
+            .take(u16::MAX as usize)
+            .take_while(|&&b| b == 0)
+            .count();
+        i += zeros;
+        let literals = xor[i..]
+            .iter()
+            .take(u16::MAX as usize)
+            .take_while(|&&b| b != 0)
+            .count();
+        out.extend_from_slice(&(zeros as u16).to_le_bytes());
+        out.extend_from_slice(&(literals as u16).to_le_bytes());
+        out.extend_from_slice(&xor[i..i + literals]);
+        i += literals;
+    }
+    out
+}
+
+fn decode(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let len = u32::from_le_bytes(delta[0..4].try_into().unwrap()) as usize;
+    let mut out = from.to_vec();
+    out.resize(from.len().max(len), 0);
+
+    let mut pos = 0;
+    let mut rest = &delta[4..];
+    while !rest.is_empty() {
+        let zeros = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+        let literals = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        pos += zeros;
+        for (byte, x) in out[pos..pos + literals]
+            .iter_mut()
+            .zip(&rest[4..4 + literals])
+        {
+            *byte ^= x;
+        }
+        pos += literals;
+        rest = &rest[4 + literals..];
+    }
+    out.truncate(len);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deltas_turn_one_snapshot_into_the_other() {
+        let from: Vec<u8> = (0..70_000).map(|i| i as u8).collect();
+        let mut changed = from.clone();
+        changed[3] ^= 0xFF;
+        changed[69_999] = 0;
+        for to in [
+            from.clone(),
+            changed,
+            from[..100].to_vec(),
+            [&from[..], &[1, 2, 3]].concat(),
+        ] {
+            assert_eq!(decode(&from, &encode(&from, &to)), to);
+        }
+    }
+
+    #[test]
+    fn small_changes_give_small_deltas() {
+        let from = vec![0xAA; 4096];
+        let mut to = from.clone();
+        to[2000] = 0x55;
+        assert!(encode(&from, &to).len() < 16);
+    }
+
+    #[test]
+    fn snapshots_come_back_newest_first_up_to_the_depth() {
+        let mut rewind = Rewind::new(3);
+        for frame in 0..5u8 {
+            rewind.push(vec![frame; 8]);
+        }
+        assert_eq!(rewind.len(), 3);
+        for frame in [4, 3, 2] {
+            assert_eq!(rewind.pop(), Some(vec![frame; 8]));
+        }
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.pop(), None);
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::Read;
+use std::path::Path;
+
+/// File extensions ROMs are commonly distributed with, used to pick the
+/// ROM out of a `.zip` archive.
+const ROM_EXTENSIONS: [&str; 5] = ["ch8", "c8", "sc8", "xo8", "rom"];
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(std::io::Error),
+    Zip(zip::result::ZipError),
+    /// The archive holds no file that looks like a ROM, or several.
+    NoRomInArchive,
+}
+
+impl std::fmt::Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "{err}"),
+            RomError::Zip(err) => write!(f, "bad zip archive: {err}"),
+            RomError::NoRomInArchive => write!(
+                f,
+                "the archive does not hold a single .ch8, .c8, .sc8, .xo8 or .rom file"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<std::io::Error> for RomError {
+    fn from(err: std::io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+impl From<zip::result::ZipError> for RomError {
+    fn from(err: zip::result::ZipError) -> Self {
+        RomError::Zip(err)
+    }
+}
+
+/// Reads a ROM image from `path`, from standard input if it is `-`, or
+/// from inside a `.zip` archive.
+pub fn read(path: &str) -> Result<Vec<u8>, RomError> {
+    if path == "-" {
+        let mut rom = Vec::new();
+        std::io::stdin().read_to_end(&mut rom)?;
+        return Ok(rom);
+    }
+    let bytes = std::fs::read(path)?;
+    if has_extension(path, &["zip"]) {
+        return read_zip(bytes);
+    }
+    Ok(bytes)
+}
+
+fn has_extension(path: &str, extensions: &[&str]) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|x| e.eq_ignore_ascii_case(x)))
+}
+
+/// Picks the only file with a ROM extension, or failing that the only file.
+fn read_zip(bytes: Vec<u8>) -> Result<Vec<u8>, RomError> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+    let files: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_owned)
+        .collect();
+    let roms: Vec<&String> = files
+        .iter()
+        .filter(|name| has_extension(name, &ROM_EXTENSIONS))
+        .collect();
+    let name = match (&roms[..], &files[..]) {
+        ([rom], _) => *rom,
+        ([], [file]) => file,
+        _ => return Err(RomError::NoRomInArchive),
+    };
+    let mut rom = Vec::new();
+    archive.by_name(name)?.read_to_end(&mut rom)?;
+    Ok(rom)
+}
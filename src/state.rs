@@ -0,0 +1,398 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+
+use crate::{
+    Framebuffer, MemoryLayout, Platform, Quirks, Random, StackConfig, CHIP8, HIRES_HEIGHT,
+    HIRES_WIDTH, PLANES,
+};
+
+const MAGIC: &[u8; 4] = b"C8SS";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    RomMismatch,
+    Corrupt,
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(v) => write!(f, "unsupported save state version {v}"),
+            StateError::RomMismatch => write!(f, "save state belongs to a different ROM"),
+            StateError::Corrupt => write!(f, "save state is truncated or corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// FNV-1a, used to tie save states to the ROM they were made with.
+pub(crate) fn rom_hash(program: &[u8]) -> u64 {
+    program.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.0.len() < n {
+            return Err(StateError::Corrupt);
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+impl Quirks {
+    fn to_bits(self) -> u16 {
+        [
+            self.fx65,
+            self.fx55,
+            self.i_8xy6,
+            self.i_8xye,
+            self.bnnn,
+            self.fx1e,
+            self.vf_reset,
+            self.wrap,
+            self.display_wait,
+            self.vf_rows,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, &flag)| bits | (flag as u16) << i)
+    }
+
+    fn from_bits(bits: u16) -> Self {
+        let flag = |i: u16| bits & (1 << i) != 0;
+        Quirks {
+            fx65: flag(0),
+            fx55: flag(1),
+            i_8xy6: flag(2),
+            i_8xye: flag(3),
+            bnnn: flag(4),
+            fx1e: flag(5),
+            vf_reset: flag(6),
+            wrap: flag(7),
+            display_wait: flag(8),
+            vf_rows: flag(9),
+        }
+    }
+}
+
+impl Platform {
+    fn to_u8(self) -> u8 {
+        match self {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        }
+    }
+
+    fn from_u8(byte: u8) -> Result<Self, StateError> {
+        match byte {
+            0 => Ok(Platform::Chip8),
+            1 => Ok(Platform::SuperChip),
+            2 => Ok(Platform::XoChip),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+}
+
+impl CHIP8 {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.ram.len() + 512);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+
+        out.extend_from_slice(&(self.pc as u16).to_le_bytes());
+        out.extend_from_slice(&self.i_reg.to_le_bytes());
+        out.extend_from_slice(&self.vx_reg);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend_from_slice(&self.quirks.to_bits().to_le_bytes());
+        out.push(self.vblank as u8);
+        out.push(self.platform.to_u8());
+        out.push(self.hires as u8);
+        out.extend_from_slice(&self.rpl_flags);
+        out.push(self.selected_planes);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+        out.extend_from_slice(&self.cycle_overrun.to_le_bytes());
+        // Only a seeded source can be recreated; a custom one is left as it
+        // is on load.
+        match &self.rng {
+            Random::Seeded { seed, rng } => {
+                out.push(1);
+                out.extend_from_slice(&seed.to_le_bytes());
+                out.extend_from_slice(&rng.get_word_pos().to_le_bytes());
+            }
+            Random::Custom(_) => out.push(0),
+        }
+
+        out.extend_from_slice(&self.layout.program_start.to_le_bytes());
+        out.extend_from_slice(&self.layout.font_address.to_le_bytes());
+        out.extend_from_slice(&(self.layout.memory_size as u32).to_le_bytes());
+        out.push(self.layout.initial_sound_timer);
+        out.extend_from_slice(&(self.stack_config.limit as u32).to_le_bytes());
+        out.push(self.stack_config.in_ram as u8);
+
+        out.extend_from_slice(&(self.stack.len() as u16).to_le_bytes());
+        for address in &self.stack {
+            out.extend_from_slice(&address.to_le_bytes());
+        }
+
+        out.extend_from_slice(&self.ram);
+
+        for row in self.planes.iter().flatten() {
+            for chunk in row.chunks(8) {
+                out.push(chunk.iter().fold(0, |byte, &lit| byte << 1 | lit as u8));
+            }
+        }
+        out
+    }
+
+    /// Restores a state produced by `save_state`. The machine is left
+    /// untouched if the state is rejected.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut r = Reader(state);
+        if r.take(4).map_err(|_| StateError::BadMagic)? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if r.u64()? != self.rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+
+        let pc = r.u16()? as usize;
+        let i_reg = r.u16()?;
+        let vx_reg: [u8; 16] = r.take(16)?.try_into().unwrap();
+        let delay_timer = r.u8()?;
+        let sound_timer = r.u8()?;
+        let quirks = Quirks::from_bits(r.u16()?);
+        let vblank = r.u8()? != 0;
+        let platform = Platform::from_u8(r.u8()?)?;
+        let hires = r.u8()? != 0;
+        let rpl_flags: [u8; 16] = r.take(16)?.try_into().unwrap();
+        let selected_planes = r.u8()?;
+        let audio_pattern: [u8; 16] = r.take(16)?.try_into().unwrap();
+        let pitch = r.u8()?;
+        let cycle_overrun = r.u32()?;
+        let rng = match r.u8()? {
+            0 => None,
+            1 => {
+                let seed = r.u64()?;
+                let mut rng = ChaCha12Rng::seed_from_u64(seed);
+                rng.set_word_pos(u128::from_le_bytes(r.take(16)?.try_into().unwrap()));
+                Some(Random::Seeded {
+                    seed,
+                    rng: Box::new(rng),
+                })
+            }
+            _ => return Err(StateError::Corrupt),
+        };
+
+        let layout = MemoryLayout {
+            program_start: r.u16()?,
+            font_address: r.u16()?,
+            memory_size: r.u32()? as usize,
+            initial_sound_timer: r.u8()?,
+        };
+        if layout.validate(platform).is_err() {
+            return Err(StateError::Corrupt);
+        }
+        let stack_config = StackConfig {
+            limit: r.u32()? as usize,
+            in_ram: r.u8()? != 0,
+        };
+        if stack_config.validate(platform, layout).is_err() {
+            return Err(StateError::Corrupt);
+        }
+
+        let stack_len = r.u16()? as usize;
+        if stack_len > stack_config.limit {
+            return Err(StateError::Corrupt);
+        }
+        let stack = (0..stack_len)
+            .map(|_| r.u16())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let ram = r.take(layout.memory_size)?;
+
+        let mut planes: [Framebuffer; PLANES] = [[[false; HIRES_WIDTH]; HIRES_HEIGHT]; PLANES];
+        for row in planes.iter_mut().flatten() {
+            for chunk in row.chunks_mut(8) {
+                let byte = r.u8()?;
+                for (bit, pixel) in chunk.iter_mut().enumerate() {
+                    *pixel = byte & (0x80 >> bit) != 0;
+                }
+            }
+        }
+        if !r.0.is_empty() {
+            return Err(StateError::Corrupt);
+        }
+
+        self.pc = pc;
+        self.i_reg = i_reg;
+        self.vx_reg = vx_reg;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.quirks = quirks;
+        self.vblank = vblank;
+        self.platform = platform;
+        self.layout = layout;
+        self.stack_config = stack_config;
+        self.hires = hires;
+        self.rpl_flags = rpl_flags;
+        self.selected_planes = selected_planes;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.cycle_overrun = cycle_overrun;
+        if let Some(rng) = rng {
+            self.rng = rng;
+        }
+        self.stack = stack;
+        self.ram = ram.to_vec();
+        self.planes = planes;
+        // A breakpoint stop belongs to the machine that was replaced.
+        self.resume_from = None;
+        self.watch_hit = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CHIP8Input;
+
+    #[test]
+    fn save_states_restore_the_whole_machine() {
+        // 6005: v0 := 5, A20A: i := 0x20A, D002: sprite v0 v0 2, 220C: call
+        // 0x20C, 1208: jump to itself, two bytes of sprite, 00EE: return.
+        let program = [
+            0x60, 0x05, 0xA2, 0x0A, 0xD0, 0x02, 0x22, 0x0C, 0x12, 0x08, 0xF0, 0x90, 0x00, 0xEE,
+        ];
+        let mut chip8 = CHIP8::new(Platform::XoChip, Quirks::default());
+        chip8.load_program(&program).unwrap();
+        for _ in 0..4 {
+            chip8.update(CHIP8Input::default()).unwrap();
+        }
+        let state = chip8.save_state();
+        let (pc, stack, framebuffer) = (chip8.pc, chip8.stack.clone(), chip8.planes);
+
+        for _ in 0..3 {
+            chip8.update(CHIP8Input::default()).unwrap();
+        }
+        chip8.vx_reg[0] = 0;
+        chip8.ram[0x300] = 1;
+        chip8.load_state(&state).unwrap();
+        assert_eq!((chip8.pc, &chip8.stack, chip8.vx_reg[0]), (pc, &stack, 5));
+        assert_eq!(chip8.ram[0x300], 0);
+        assert!(chip8.planes == framebuffer);
+        assert_eq!(chip8.save_state(), state);
+    }
+
+    #[test]
+    fn save_states_continue_the_random_stream_and_frame_timing() {
+        // C0FF: v0 := random 0xFF, 1200: jump 0x200
+        let program = [0xC0, 0xFF, 0x12, 0x00];
+        let draws = |chip8: &mut CHIP8| -> Vec<u8> {
+            (0..8)
+                .map(|_| {
+                    chip8.run_cycles(2, &CHIP8Input::default()).unwrap();
+                    chip8.vx_reg[0]
+                })
+                .collect()
+        };
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8.load_program(&program).unwrap();
+        chip8.seed_rng(7);
+        chip8.run_vip_frame(&CHIP8Input::default()).unwrap();
+        let state = chip8.save_state();
+        let expected = draws(&mut chip8);
+
+        let mut restored = CHIP8::new(Platform::Chip8, Quirks::default());
+        restored.load_program(&program).unwrap();
+        restored.seed_rng(99);
+        restored.resume_from = Some(0x200);
+        restored.load_state(&state).unwrap();
+        // 14 loops of 184 cycles and one more CXNN of 104 overrun by 80.
+        assert_eq!(restored.cycle_overrun, 80);
+        assert_eq!(restored.resume_from, None);
+        assert_eq!(draws(&mut restored), expected);
+    }
+
+    #[test]
+    fn states_are_rejected_without_touching_the_machine() {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        chip8.load_program(&[0x12, 0x00]).unwrap();
+        let state = chip8.save_state();
+        chip8.vx_reg[0] = 9;
+
+        assert_eq!(chip8.load_state(b"nope"), Err(StateError::BadMagic));
+        assert_eq!(
+            chip8.load_state(&state[..state.len() - 1]),
+            Err(StateError::Corrupt)
+        );
+        let mut version = state.clone();
+        version[4] = VERSION + 1;
+        assert_eq!(
+            chip8.load_state(&version),
+            Err(StateError::UnsupportedVersion(VERSION + 1))
+        );
+        let mut other = CHIP8::new(Platform::Chip8, Quirks::default());
+        other.load_program(&[0x12, 0x02, 0x12, 0x02]).unwrap();
+        assert_eq!(other.load_state(&state), Err(StateError::RomMismatch));
+        assert_eq!(chip8.vx_reg[0], 9);
+    }
+
+    #[test]
+    fn deep_stacks_survive_a_save_state() {
+        let mut chip8 = CHIP8::new(Platform::Chip8, Quirks::default());
+        // 2200: call 2200, calling itself until 300 calls are on the stack.
+        chip8.load_program(&[0x22, 0x00]).unwrap();
+        chip8
+            .set_stack_config(StackConfig {
+                limit: 300,
+                in_ram: false,
+            })
+            .unwrap();
+        for _ in 0..300 {
+            chip8.update(CHIP8Input::default()).unwrap();
+        }
+        let state = chip8.save_state();
+
+        let mut restored = CHIP8::new(Platform::Chip8, Quirks::default());
+        restored.load_program(&[0x22, 0x00]).unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.stack.len(), 300);
+        assert_eq!(restored.save_state(), state);
+    }
+}
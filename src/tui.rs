@@ -0,0 +1,422 @@
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::app::Debugger;
+use crate::debug::{parse_number, Condition, OpcodePattern, Watchpoint};
+use crate::{decode_at, CHIP8Input, Instruction, Status, CHIP8};
+
+const HELP: &str = "s step  n step over  r run to cursor  c continue  p pause  \
+    b breakpoint  up/down cursor  PgUp/PgDn memory  : command  q quit";
+
+const COMMANDS: &str = "Commands: break ADDR, break-opcode PATTERN, watch SPEC, \
+    if EXPR, log EXPR \"MESSAGE\", delete [ADDR], goto ADDR, mem ADDR";
+
+/// How many of the latest messages are kept on screen.
+const MESSAGES: usize = 4;
+
+/// Rows of 16 bytes in the memory view.
+const MEMORY_ROWS: u16 = 8;
+
+/// Full-screen terminal debugger that `chip8 debug` runs beside the
+/// emulator window.
+pub struct TerminalDebugger {
+    terminal: DefaultTerminal,
+    view: View,
+}
+
+impl TerminalDebugger {
+    pub fn new() -> io::Result<TerminalDebugger> {
+        Ok(TerminalDebugger {
+            terminal: ratatui::try_init()?,
+            view: View::default(),
+        })
+    }
+}
+
+impl Drop for TerminalDebugger {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+impl Debugger for TerminalDebugger {
+    fn frame(&mut self, chip8: &mut CHIP8, input: &CHIP8Input, paused: &mut bool) -> bool {
+        if !self.view.started {
+            self.view.started = true;
+            self.view.memory_top = chip8.pc() & !0xF;
+            *paused = true;
+        }
+        if *paused {
+            if let Some(address) = self.view.temporary.take() {
+                chip8.breakpoints_mut().addresses.remove(&address);
+            }
+        }
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                if key.kind == KeyEventKind::Press && !self.view.key(key.code, chip8, input, paused)
+                {
+                    return false;
+                }
+            }
+        }
+        let view = &mut self.view;
+        let _ = self.terminal.draw(|frame| view.draw(frame, chip8, *paused));
+        true
+    }
+
+    fn message(&mut self, text: String) {
+        self.view.message(text);
+    }
+}
+
+/// Everything the debugger shows besides the machine itself.
+#[derive(Default)]
+struct View {
+    started: bool,
+    /// Selected line of the disassembly.
+    cursor: usize,
+    /// First address of the disassembly, kept so that the cursor is visible.
+    top: usize,
+    /// The disassembly follows `pc` whenever it moves.
+    last_pc: Option<usize>,
+    memory_top: usize,
+    /// Text typed after `:`, while a command is being entered.
+    command: Option<String>,
+    messages: VecDeque<String>,
+    /// Breakpoint set by step over and run to cursor, removed on the next
+    /// stop.
+    temporary: Option<u16>,
+}
+
+impl View {
+    fn message(&mut self, text: String) {
+        self.messages.push_back(text);
+        if self.messages.len() > MESSAGES {
+            self.messages.pop_front();
+        }
+    }
+
+    /// Handles a key press. Returns false to quit.
+    fn key(
+        &mut self,
+        key: KeyCode,
+        chip8: &mut CHIP8,
+        input: &CHIP8Input,
+        paused: &mut bool,
+    ) -> bool {
+        if let Some(command) = &mut self.command {
+            match key {
+                KeyCode::Char(c) => command.push(c),
+                KeyCode::Backspace => {
+                    command.pop();
+                }
+                KeyCode::Esc => self.command = None,
+                KeyCode::Enter => {
+                    let line = self.command.take().unwrap();
+                    let text = self.run_command(&line, chip8).unwrap_or_else(|err| err);
+                    self.message(text);
+                }
+                _ => {}
+            }
+            return true;
+        }
+        match key {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('s') | KeyCode::F(11) => self.step(chip8, input, paused),
+            KeyCode::Char('n') | KeyCode::F(10) => match decode_at(chip8.memory(), chip8.pc()) {
+                Ok(Instruction::Call(_)) => self.run_to(chip8, chip8.pc() + 2, paused),
+                _ => self.step(chip8, input, paused),
+            },
+            KeyCode::Char('r') | KeyCode::F(4) => self.run_to(chip8, self.cursor, paused),
+            KeyCode::Char('c') | KeyCode::F(5) => {
+                chip8.resume();
+                *paused = false;
+            }
+            KeyCode::Char('p') => *paused = true,
+            KeyCode::Char('b') | KeyCode::F(9) => {
+                let address = self.cursor as u16;
+                let addresses = &mut chip8.breakpoints_mut().addresses;
+                if !addresses.remove(&address) {
+                    addresses.insert(address);
+                }
+            }
+            KeyCode::Up => self.cursor = self.cursor.saturating_sub(2),
+            KeyCode::Down => {
+                let len = decode_at(chip8.memory(), self.cursor).map_or(2, |i| i.len());
+                self.cursor = (self.cursor + len).min(chip8.memory().len() - 2);
+            }
+            KeyCode::PageUp => {
+                self.memory_top = self.memory_top.saturating_sub(16 * MEMORY_ROWS as usize)
+            }
+            KeyCode::PageDown => {
+                let last = chip8.memory().len() - 16 * MEMORY_ROWS as usize;
+                self.memory_top = (self.memory_top + 16 * MEMORY_ROWS as usize).min(last);
+            }
+            KeyCode::Char(':') => self.command = Some(String::new()),
+            _ => {}
+        }
+        true
+    }
+
+    /// Executes the instruction at `pc`, even if it has a breakpoint.
+    fn step(&mut self, chip8: &mut CHIP8, input: &CHIP8Input, paused: &mut bool) {
+        *paused = true;
+        chip8.resume();
+        match chip8.update(input.clone()) {
+            Ok(out) => {
+                for text in out.log {
+                    self.message(text);
+                }
+                if let Status::Stopped(reason) = out.status {
+                    self.message(format!("Stopped: {reason}."));
+                }
+            }
+            Err(err) => self.message(format!("Emulation halted: {err}")),
+        }
+    }
+
+    fn run_to(&mut self, chip8: &mut CHIP8, address: usize, paused: &mut bool) {
+        let Ok(address) = u16::try_from(address) else {
+            return;
+        };
+        if chip8.breakpoints_mut().addresses.insert(address) {
+            self.temporary = Some(address);
+        }
+        chip8.resume();
+        *paused = false;
+    }
+
+    fn run_command(&mut self, line: &str, chip8: &mut CHIP8) -> Result<String, String> {
+        let (name, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let argument = argument.trim();
+        let memory_size = chip8.memory().len();
+        let address = || {
+            parse_number(argument)
+                .filter(|&address| address < memory_size)
+                .ok_or(format!("\"{argument}\" is not an address."))
+        };
+        let breakpoints = chip8.breakpoints_mut();
+        match name {
+            "break" | "b" => {
+                let address = address()?;
+                breakpoints.addresses.insert(address as u16);
+                Ok(format!("Breakpoint at {address:#05X}."))
+            }
+            "break-opcode" => {
+                let pattern = OpcodePattern::parse(argument)
+                    .ok_or(format!("\"{argument}\" is not an opcode pattern."))?;
+                breakpoints.opcodes.push(pattern);
+                Ok(format!("Breakpoint on opcode {pattern}."))
+            }
+            "watch" | "w" => {
+                let watchpoint = Watchpoint::parse(argument)
+                    .ok_or(format!("\"{argument}\" is not a register or memory range."))?;
+                let text = format!("Watching {watchpoint}.");
+                breakpoints.watchpoints.push(watchpoint);
+                Ok(text)
+            }
+            "if" => {
+                let condition = Condition::new(argument, None)?;
+                let text = format!("Breaking on {condition}.");
+                breakpoints.conditions.push(condition);
+                Ok(text)
+            }
+            "log" => {
+                let (expr, message) = argument
+                    .split_once('"')
+                    .ok_or("A log point needs a message in quotes.")?;
+                let message = message.strip_suffix('"').unwrap_or(message);
+                let condition = Condition::new(expr, Some(message))?;
+                let text = format!("Added {condition}.");
+                breakpoints.conditions.push(condition);
+                Ok(text)
+            }
+            "delete" | "d" if argument.is_empty() => {
+                *breakpoints = Default::default();
+                Ok("Deleted all breakpoints and watchpoints.".to_owned())
+            }
+            "delete" | "d" => {
+                let address = address()?;
+                match breakpoints.addresses.remove(&(address as u16)) {
+                    true => Ok(format!("Deleted the breakpoint at {address:#05X}.")),
+                    false => Err(format!("There is no breakpoint at {address:#05X}.")),
+                }
+            }
+            "goto" | "g" => {
+                self.cursor = address()?;
+                Ok(format!("Showing {:#05X}.", self.cursor))
+            }
+            "mem" | "m" => {
+                let address = address()?;
+                self.memory_top = (address & !0xF).min(memory_size - 16);
+                Ok(format!("Showing memory at {address:#05X}."))
+            }
+            _ => Err(COMMANDS.to_owned()),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame, chip8: &CHIP8, paused: bool) {
+        if self.last_pc != Some(chip8.pc()) {
+            self.last_pc = Some(chip8.pc());
+            self.cursor = chip8.pc();
+        }
+        let [main, memory, messages, help] = Layout::vertical([
+            Constraint::Min(8),
+            Constraint::Length(MEMORY_ROWS + 2),
+            Constraint::Length(MESSAGES as u16),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [code, side] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .areas(main);
+        let [registers, stack] =
+            Layout::vertical([Constraint::Length(9), Constraint::Min(3)]).areas(side);
+
+        self.draw_code(frame, code, chip8);
+        draw_registers(frame, registers, chip8, paused);
+        draw_stack(frame, stack, chip8);
+        self.draw_memory(frame, memory, chip8);
+
+        let lines: Vec<Line> = self.messages.iter().map(Line::raw).collect();
+        frame.render_widget(Paragraph::new(lines), messages);
+        let help_line = match &self.command {
+            Some(command) => Line::raw(format!(":{command}")),
+            None => Line::raw(HELP).dark_gray(),
+        };
+        frame.render_widget(Paragraph::new(help_line), help);
+    }
+
+    fn draw_code(&mut self, frame: &mut Frame, area: Rect, chip8: &CHIP8) {
+        let rows = area.height.saturating_sub(2) as usize;
+        let memory = chip8.memory();
+        // Instructions are two bytes apart for all but XO-CHIP's F000 NNNN,
+        // so twice the rows is about the last address on screen.
+        if self.cursor < self.top || self.cursor >= self.top + rows * 2 {
+            self.top = self.cursor - (rows / 3 * 2).min(self.cursor & !1);
+        }
+        let mut lines = Vec::new();
+        let mut address = self.top;
+        while lines.len() < rows && address + 1 < memory.len() {
+            let (text, len) = match decode_at(memory, address) {
+                Ok(instruction) => (instruction.to_string(), instruction.len()),
+                Err(_) => (format!("DW {:#06X}", read_word(memory, address)), 2),
+            };
+            let bytes: String = memory[address..address + len]
+                .iter()
+                .map(|b| format!("{b:02X}"))
+                .collect();
+            let marker = match (
+                address == chip8.pc(),
+                chip8.breakpoints().addresses.contains(&(address as u16)),
+            ) {
+                (true, true) => Span::raw("*>").red().bold(),
+                (true, false) => Span::raw(" >").yellow().bold(),
+                (false, true) => Span::raw("* ").red(),
+                (false, false) => Span::raw("  "),
+            };
+            let mut line = Line::from(vec![
+                marker,
+                Span::raw(format!(" {address:03X}  {bytes:<8}  {text}")),
+            ]);
+            if address == self.cursor {
+                line = line.reversed();
+            }
+            lines.push(line);
+            address += len;
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Disassembly ")),
+            area,
+        );
+    }
+
+    fn draw_memory(&self, frame: &mut Frame, area: Rect, chip8: &CHIP8) {
+        let memory = chip8.memory();
+        let i = chip8.i_reg() as usize;
+        let pc = chip8.pc();
+        let mut lines = Vec::new();
+        for row in (self.memory_top..memory.len())
+            .step_by(16)
+            .take(MEMORY_ROWS as usize)
+        {
+            let mut spans = vec![Span::raw(format!("{row:04X} "))];
+            for (address, byte) in memory.iter().enumerate().skip(row).take(16) {
+                let span = Span::raw(format!(" {byte:02X}"));
+                spans.push(if address == i {
+                    span.reversed()
+                } else if (pc..pc + 2).contains(&address) {
+                    span.yellow()
+                } else {
+                    span
+                });
+            }
+            lines.push(Line::from(spans));
+        }
+        let title = format!(" Memory (I = {i:#05X}) ");
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+}
+
+fn read_word(memory: &[u8], address: usize) -> u16 {
+    u16::from_be_bytes([memory[address], memory[address + 1]])
+}
+
+fn draw_registers(frame: &mut Frame, area: Rect, chip8: &CHIP8, paused: bool) {
+    let registers = chip8.registers();
+    let mut lines: Vec<Line> = registers
+        .chunks(4)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let cells: Vec<String> = chunk
+                .iter()
+                .enumerate()
+                .map(|(column, value)| format!("V{:X} {value:02X}", row * 4 + column))
+                .collect();
+            Line::raw(cells.join("  "))
+        })
+        .collect();
+    lines.push(Line::raw(format!(
+        "I  {:03X}  PC {:03X}",
+        chip8.i_reg(),
+        chip8.pc()
+    )));
+    lines.push(Line::raw(format!(
+        "DT {:02X}   ST {:02X}",
+        chip8.delay_timer(),
+        chip8.sound_timer()
+    )));
+    lines.push(match paused {
+        true => Line::styled("Paused", Style::new().fg(Color::Yellow)),
+        false => Line::styled("Running", Style::new().fg(Color::Green)),
+    });
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Registers ")),
+        area,
+    );
+}
+
+fn draw_stack(frame: &mut Frame, area: Rect, chip8: &CHIP8) {
+    let lines: Vec<Line> = chip8
+        .stack()
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(depth, address)| Line::raw(format!("{depth:2}  {address:03X}")))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Stack ")),
+        area,
+    );
+}
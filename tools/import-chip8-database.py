@@ -0,0 +1,76 @@
+#!/usr/bin/env python3
+"""Converts programs.json from https://github.com/chip-8/chip-8-database into
+entries for src/database.txt, printed to stdout.
+
+    tools/import-chip8-database.py path/to/database/programs.json >> src/database.txt
+
+ROMs that only run on platforms this emulator lacks are left out.
+"""
+
+import json
+import sys
+
+PLATFORMS = {
+    "originalChip8": "vip",
+    "hybridVIP": "vip",
+    "modernChip8": "chip8",
+    "chip48": "chip48",
+    "superchip1": "schip-legacy",
+    "superchip": "schip",
+    "xochip": "xochip",
+}
+
+
+def quirks(settings):
+    """Quirk overrides in the database.txt syntax for a quirkyPlatforms entry."""
+    flags = []
+    if "shift" in settings:
+        flags += [("8xy6", not settings["shift"]), ("8xye", not settings["shift"])]
+    if settings.get("memoryLeaveIUnchanged"):
+        flags += [("fx55", False), ("fx65", False)]
+    elif settings.get("memoryLeaveIUnchanged") is False and not settings.get("memoryIncrementByX"):
+        flags += [("fx55", True), ("fx65", True)]
+    if "jump" in settings:
+        flags.append(("bnnn", not settings["jump"]))
+    for ours, theirs in [("wrap", "wrap"), ("vblank", "vblank"), ("vfreset", "logic")]:
+        if theirs in settings:
+            flags.append((ours, settings[theirs]))
+    return " ".join(f"{name}={'on' if value else 'off'}" for name, value in flags)
+
+
+def entry(title, sha1, rom):
+    platform = next((p for p in rom.get("platforms", []) if p in PLATFORMS), None)
+    if platform is None:
+        return None
+    lines = [f"[{sha1.lower()}]", f"title = {title}", f"platform = {PLATFORMS[platform]}"]
+    if "tickrate" in rom:
+        lines.append(f"ipf = {rom['tickrate']}")
+    overrides = quirks(rom.get("quirkyPlatforms", {}).get(platform, {}))
+    if overrides:
+        lines.append(f"quirks = {overrides}")
+    pixels = rom.get("colors", {}).get("pixels", [])
+    if pixels:
+        lines.append("palette = " + " ".join(color.lstrip("#").lower() for color in pixels))
+    keys = rom.get("keys", {})
+    if keys:
+        hint = ", ".join(f"{name} {key:X}" for name, key in keys.items())
+        lines.append(f"keys = {hint}")
+    return "\n".join(lines)
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(f"usage: {sys.argv[0]} programs.json")
+    with open(sys.argv[1], encoding="utf-8") as file:
+        programs = json.load(file)
+    for program in programs:
+        title = " ".join(program["title"].split())
+        for sha1, rom in program.get("roms", {}).items():
+            converted = entry(title, sha1, rom)
+            if converted:
+                print()
+                print(converted)
+
+
+if __name__ == "__main__":
+    main()